@@ -0,0 +1,125 @@
+use serde::Deserialize;
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Config - settings for tsd, read from a TOML file. Every setting is
+/// optional, and falls back to a sensible default if it's not given.
+#[derive(Deserialize, Default, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub database_path: Option<PathBuf>,
+    pub default_tags: Vec<String>, // Added to every link, along with any given with -t
+}
+
+/// ConfigError - Something went wrong while working out our configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    Io(PathBuf, io::Error),
+    Malformed(PathBuf, toml::de::Error),
+    NoDefaultPath,
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, e) => write!(f, "Couldn't read {}: {}", path.display(), e),
+            ConfigError::Malformed(path, e) => {
+                write!(f, "Malformed config file {}: {}", path.display(), e)
+            }
+            ConfigError::NoDefaultPath => write!(
+                f,
+                "Couldn't find a default location for the database, please set database_path"
+            ),
+        }
+    }
+}
+
+impl Config {
+    /// Load the config from the given file, or from the default location if
+    /// none is given. A config file missing from the default location just
+    /// means we use the default settings, whereas one that's been asked for
+    /// explicitly has to exist.
+    pub fn load(path: Option<&Path>) -> Result<Config, ConfigError> {
+        match path {
+            Some(path) => Config::read(path),
+            None => match default_config_path() {
+                Some(path) if path.exists() => Config::read(&path),
+                _ => Ok(Config::default()),
+            },
+        }
+    }
+
+    fn read(path: &Path) -> Result<Config, ConfigError> {
+        let contents =
+            fs::read_to_string(path).map_err(|e| ConfigError::Io(path.to_path_buf(), e))?;
+        toml::from_str(&contents).map_err(|e| ConfigError::Malformed(path.to_path_buf(), e))
+    }
+
+    /// The database to use, either as configured, or the default location.
+    pub fn database_path(&self) -> Result<PathBuf, ConfigError> {
+        match &self.database_path {
+            Some(path) => Ok(path.clone()),
+            None => dirs::data_dir()
+                .map(|dir| dir.join("tsundoku").join("tsd.db"))
+                .ok_or(ConfigError::NoDefaultPath),
+        }
+    }
+}
+
+// Where we look for a config file, if we're not told to look elsewhere,
+// e.g. ~/.config/tsundoku/config.toml
+fn default_config_path() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("tsundoku").join("config.toml"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn load_str(contents: &str) -> Result<Config, ConfigError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, contents).unwrap();
+        Config::load(Some(&path))
+    }
+
+    #[test]
+    fn load_database_path() {
+        let config = load_str("database_path = \"/tmp/pile.db\"").unwrap();
+        assert_eq!(
+            config.database_path().unwrap(),
+            PathBuf::from("/tmp/pile.db")
+        );
+    }
+
+    #[test]
+    fn load_default_tags() {
+        let config = load_str("default_tags = [\"inbox\", \"unsorted\"]").unwrap();
+        assert_eq!(config.default_tags, vec!["inbox", "unsorted"]);
+    }
+
+    #[test]
+    fn load_empty_uses_defaults() {
+        assert_eq!(load_str("").unwrap(), Config::default());
+    }
+
+    #[test]
+    fn load_malformed_fails() {
+        match load_str("database_path = ") {
+            Err(ConfigError::Malformed(_, _)) => {}
+            other => panic!("expected a malformed config error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn load_missing_explicit_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        match Config::load(Some(&dir.path().join("missing.toml"))) {
+            Err(ConfigError::Io(_, _)) => {}
+            other => panic!("expected an io error, got {:?}", other),
+        }
+    }
+}
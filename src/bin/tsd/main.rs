@@ -0,0 +1,765 @@
+extern crate tsundoku;
+
+use tsundoku::datamodel::{Archive, Database, Datastore, EntryAddResult, EntryBuilder, OwnedEntry};
+use tsundoku::error;
+
+#[macro_use]
+extern crate clap;
+use clap::ArgMatches;
+
+#[macro_use]
+extern crate log;
+use log::LevelFilter;
+
+use serde::Serialize;
+use url::Url;
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::{self, Command};
+
+mod config;
+use config::Config;
+
+fn main() {
+    let matches = clap_app!(myapp =>
+        (version: "0.0.1") // Use semver https://semver.org/
+        (author: "Adam H <harries.adam@gmail.com>")
+        (about: "blesh")
+        (@setting SubcommandRequiredElseHelp)
+        (@arg CONFIG: -c --config +takes_value "Sets a custom config file")
+        (@arg debug: -d ... "Sets the level of debugging information")
+        (@arg json: --json "Print results as JSON, rather than for humans to read")
+        (@subcommand add =>
+            (about: "Add a link or reference to a piece to the pile.")
+            (version: "0.0.1") // use semver
+            (@arg LINK: required_unless[stdin] "The link or reference to add to the pile.")
+            (@arg COMMENT: -c --comment +takes_value "A comment on the link for later reference")
+            (@arg stdin: --stdin conflicts_with[LINK COMMENT] "Add every link read from stdin, one per line")
+            (@arg TAGS: -t --tags +takes_value "A comma separated list of tags to associate with the link")
+        )
+        (@subcommand read =>
+            (about: "Pull a link from the dump, mark it as read, and add it to the archive.")
+            (version: "0.0.1") //use semver
+            (@arg ID: +required {is_id} "The ID of the link to read")
+        )
+        (@subcommand requeue =>
+            (about: "Move a link from the archive back into the pile, to read it again.")
+            (version: "0.0.1") //use semver
+            (@arg ID: +required {is_id} "The ID of the link to requeue")
+        )
+        (@subcommand bored =>
+            (about: "Find something to read, by picking a random link from the pile.")
+            (version: "0.0.1") //use semver
+            (@arg TAG: -t --tag +takes_value "Only pick links tagged with this tag")
+            (@arg interactive: -i --interactive conflicts_with[TAG] "Keep picking links, asking whether to read or skip each one")
+        )
+        (@subcommand next =>
+            (about: "Find something to read, by picking the oldest link in the pile.")
+            (version: "0.0.1") //use semver
+        )
+        (@subcommand open =>
+            (about: "Open a link from the pile in the browser, and add it to the archive.")
+            (version: "0.0.1") //use semver
+            (@arg ID: +required {is_id} "The ID of the link to open")
+            (@arg keep: -k --keep "Keep the link in the pile, rather than archiving it")
+        )
+        (@subcommand search =>
+            (about: "Search the links and comments in the pile.")
+            (version: "0.0.1") //use semver
+            (@arg QUERY: +required "The text to look for, ignoring case")
+            (@arg TAG: -t --tag +takes_value "Only search links tagged with this tag")
+        )
+        (@subcommand recent =>
+            (about: "List the links most recently added to the pile, read or not.")
+            (version: "0.0.1") //use semver
+            (@arg N: {is_count} "How many links to list (default 10)")
+        )
+        (@subcommand untagged =>
+            (about: "List the links in the pile that don't have any tags, read or not.")
+            (version: "0.0.1") //use semver
+        )
+        (@subcommand stats =>
+            (about: "Summarise what's in the pile.")
+            (version: "0.0.1") //use semver
+        )
+        (@subcommand archive_all =>
+            (name: "archive-all")
+            (about: "Declare reading list bankruptcy, moving every link in the pile to the archive.")
+            (version: "0.0.1") //use semver
+            (@arg yes: -y --yes "Don't ask for confirmation first")
+            (@arg dry_run: --("dry-run") "Show which links would be moved, without moving them")
+        )
+        (@subcommand prune =>
+            (about: "Remove tags that aren't used by any links.")
+            (version: "0.0.1") //use semver
+            (@arg dry_run: --("dry-run") "Show which tags would be removed, without removing them")
+        )
+        (@subcommand dedupe =>
+            (about: "Merge links that are in the pile more than once, keeping all of their tags.")
+            (version: "0.0.1") //use semver
+            (@arg dry_run: --("dry-run") "Show which links would be merged, without merging them")
+        )
+        (@subcommand vacuum =>
+            (about: "Compact the database, reclaiming space from deleted links.")
+            (version: "0.0.1") //use semver
+        )
+        (@subcommand backup =>
+            (about: "Write a copy of the database to a file.")
+            (version: "0.0.1") //use semver
+            (@arg PATH: +required "Where to write the copy")
+        )
+        (@subcommand list =>
+            (about: "List the links in the pile.")
+            (version: "0.0.1") //use semver
+            (@arg TAG: -t --tag +takes_value "Only list links tagged with this tag")
+            (@arg archived: -a --archived "List links in the archive, rather than the pile")
+        )
+    )
+    .get_matches();
+
+    env_logger::Builder::new()
+        .filter_level(log_level(matches.occurrences_of("debug")))
+        .init();
+
+    let config = match Config::load(matches.value_of("CONFIG").map(Path::new)) {
+        Ok(config) => config,
+        Err(e) => fail(&e.to_string()),
+    };
+    let db = open_database(&config);
+    let json = matches.is_present("json");
+
+    match matches.subcommand() {
+        ("add", Some(add_matches)) => add(&db, add_matches, &config.default_tags),
+        ("read", Some(read_matches)) => read(&db, read_matches),
+        ("requeue", Some(requeue_matches)) => requeue(&db, requeue_matches),
+        ("bored", Some(bored_matches)) => bored(&db, bored_matches, json),
+        ("next", Some(_)) => next(&db, json),
+        ("list", Some(list_matches)) => list(&db, list_matches, json),
+        ("open", Some(open_matches)) => open(&db, open_matches),
+        ("search", Some(search_matches)) => search(&db, search_matches, json),
+        ("recent", Some(recent_matches)) => recent(&db, recent_matches, json),
+        ("untagged", Some(_)) => untagged(&db, json),
+        ("stats", Some(_)) => stats(&db, json),
+        ("archive-all", Some(archive_matches)) => archive_all(&db, archive_matches),
+        ("prune", Some(prune_matches)) => prune(&db, prune_matches.is_present("dry_run")),
+        ("dedupe", Some(dedupe_matches)) => dedupe(&db, dedupe_matches.is_present("dry_run")),
+        ("vacuum", Some(_)) => vacuum(&db),
+        ("backup", Some(backup_matches)) => backup(&db, backup_matches),
+        _ => {}
+    }
+}
+
+// How much to log, given how many times the debug flag was passed: with none
+// we only log warnings and errors, and each one after that logs more.
+fn log_level(debug: u64) -> LevelFilter {
+    match debug {
+        0 => LevelFilter::Warn,
+        1 => LevelFilter::Info,
+        2 => LevelFilter::Debug,
+        _ => LevelFilter::Trace,
+    }
+}
+
+// Open the configured database, creating it (and its directory) if needed.
+fn open_database(config: &Config) -> Database {
+    let path = match config.database_path() {
+        Ok(path) => path,
+        Err(e) => fail(&e.to_string()),
+    };
+    debug!("using the database at {}", path.display());
+    if let Some(dir) = path.parent() {
+        if let Err(e) = fs::create_dir_all(dir) {
+            fail(&format!("Couldn't create {}: {}", dir.display(), e));
+        }
+    }
+    match Database::open_from_path(&path) {
+        Ok(db) => db,
+        Err(e) => fail(&format!(
+            "Couldn't open the database {}: {}",
+            path.display(),
+            e
+        )),
+    }
+}
+
+fn add(db: &Database, matches: &ArgMatches, default_tags: &[String]) {
+    let tags = with_default_tags(
+        matches.value_of("TAGS").map(split_tags).unwrap_or_default(),
+        default_tags,
+    );
+    if matches.is_present("stdin") {
+        return add_from_stdin(db, &tags);
+    }
+
+    let mut builder = EntryBuilder::new()
+        .link(matches.value_of("LINK").unwrap()) // LINK is required without --stdin, so clap ensures it's there
+        .tags(tags)
+        .source("manual");
+    if let Some(comment) = matches.value_of("COMMENT").filter(|c| !c.is_empty()) {
+        builder = builder.comment(comment);
+    }
+    let entry = match builder.build() {
+        Ok(entry) => entry,
+        Err(e) => fail(&format!("Couldn't add the link: {}", e)),
+    };
+
+    match db.add_entry(entry) {
+        Ok(EntryAddResult::LinkId(i)) => println!("Added to queue, id #{}", i),
+        Ok(EntryAddResult::LinkAlreadyExists) => fail("That link is already in the pile."),
+        Err(e) => fail(&format!("Couldn't add the link: {}", e)),
+    }
+}
+
+// Add each link from stdin, reporting any that we couldn't add.
+fn add_from_stdin(db: &Database, tags: &[&str]) {
+    let results = match db.add_entries_from_lines(io::stdin(), tags, "stdin") {
+        Ok(results) => results,
+        Err(e) => fail(&format!("Couldn't read links from stdin: {}", e)),
+    };
+
+    let mut added = 0;
+    for (link, result) in &results {
+        match result {
+            Ok(EntryAddResult::LinkId(_)) => added += 1,
+            Ok(EntryAddResult::LinkAlreadyExists) => {
+                eprintln!("Skipped {}, it's already in the pile.", link)
+            }
+            Err(e) => eprintln!("Couldn't add {}: {}", link, e),
+        }
+    }
+    println!("Added {} of {} links to queue.", added, results.len());
+}
+
+fn read(db: &Database, matches: &ArgMatches) {
+    let link_id = parse_id(matches.value_of("ID").unwrap()).unwrap(); // validated by clap
+
+    let entry = match db.get_entry(link_id) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => fail(&format!("There's no link with id #{}.", link_id)),
+        Err(e) => fail(&format!("Couldn't find the link: {}", e)),
+    };
+
+    match db.mark_as_read(link_id) {
+        Ok(_) => println!("Link:\n    {}\nmoved from dump to archive.", entry.link),
+        Err(e) => fail(&format!("Couldn't mark the link as read: {}", e)),
+    }
+}
+
+fn requeue(db: &Database, matches: &ArgMatches) {
+    let link_id = parse_id(matches.value_of("ID").unwrap()).unwrap(); // validated by clap
+
+    match db.requeue(link_id) {
+        Ok(true) => println!("Moved #{} from archive back to dump.", link_id),
+        Ok(false) => fail(&format!("There's no link with id #{}.", link_id)),
+        Err(e) => fail(&format!("Couldn't requeue the link: {}", e)),
+    }
+}
+
+fn bored(db: &Database, matches: &ArgMatches, json: bool) {
+    if matches.is_present("interactive") {
+        return bored_interactive(db);
+    }
+
+    let picked = match matches.value_of("TAG") {
+        Some(tag) => db.random_queued_for_tag(tag),
+        None => db.random_queued(),
+    };
+    match picked {
+        Ok(picked) if json => print_json(&picked),
+        Ok(Some(entry)) => print_entry(&entry),
+        Ok(None) if matches.is_present("TAG") => {
+            println!("There's nothing in the pile with that tag, try another!")
+        }
+        Ok(None) => println!("There's nothing in the pile, go outside!"),
+        Err(e) => fail(&format!("Couldn't pick a link: {}", e)),
+    }
+}
+
+// Walk through the queue at random, asking what to do with each link, until
+// we run out of links or the user quits. Skipped links aren't picked again.
+fn bored_interactive(db: &Database) {
+    let mut skipped = vec![];
+    loop {
+        let entry = match db.random_queued_excluding(&skipped) {
+            Ok(Some(entry)) => entry,
+            Ok(None) if skipped.is_empty() => {
+                return println!("There's nothing in the pile, go outside!")
+            }
+            Ok(None) => return println!("That's everything in the pile!"),
+            Err(e) => fail(&format!("Couldn't pick a link: {}", e)),
+        };
+        print_entry(&entry);
+
+        loop {
+            match prompt("(r)ead, (s)kip or (q)uit?").as_deref() {
+                Some("r") | Some("read") => {
+                    match db.mark_as_read(entry.id) {
+                        Ok(_) => println!("Moved from dump to archive."),
+                        Err(e) => fail(&format!("Couldn't mark the link as read: {}", e)),
+                    }
+                    break;
+                }
+                Some("s") | Some("skip") => {
+                    skipped.push(entry.id);
+                    break;
+                }
+                Some("q") | Some("quit") | None => return,
+                Some(_) => println!("Please answer r, s or q."),
+            }
+        }
+    }
+}
+
+fn next(db: &Database, json: bool) {
+    match db.next_queued() {
+        Ok(next) if json => print_json(&next),
+        Ok(Some(entry)) => print_entry(&entry),
+        Ok(None) => println!("There's nothing in the pile, go outside!"),
+        Err(e) => fail(&format!("Couldn't pick a link: {}", e)),
+    }
+}
+
+fn open(db: &Database, matches: &ArgMatches) {
+    let link_id = parse_id(matches.value_of("ID").unwrap()).unwrap(); // validated by clap
+
+    let entry = match db.get_entry(link_id) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => fail(&format!("There's no link with id #{}.", link_id)),
+        Err(e) => fail(&format!("Couldn't find the link: {}", e)),
+    };
+
+    if let Err(e) = open_in_browser(&entry.link) {
+        fail(&format!("Couldn't open {}: {}", entry.link, e));
+    }
+
+    if !matches.is_present("keep") {
+        match db.mark_as_read(link_id) {
+            Ok(_) => println!("Opened, and moved from dump to archive."),
+            Err(e) => fail(&format!("Couldn't mark the link as read: {}", e)),
+        }
+    }
+}
+
+// Open a link using the system's default browser. Links can come from
+// imports, so they're never handed to a shell, and only http(s) URLs are
+// opened, rather than paths, programs or options for the opener.
+fn open_in_browser(link: &str) -> io::Result<()> {
+    let url = match Url::parse(link) {
+        Ok(url) if url.scheme() == "http" || url.scheme() == "https" => url,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "only http and https links can be opened",
+            ))
+        }
+    };
+
+    let mut command = if cfg!(target_os = "windows") {
+        let mut command = Command::new("rundll32");
+        command.arg("url.dll,FileProtocolHandler");
+        command
+    } else if cfg!(target_os = "macos") {
+        Command::new("open")
+    } else {
+        Command::new("xdg-open")
+    };
+
+    let status = command.arg(url.as_str()).status()?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "the browser exited with {}",
+            status
+        )))
+    }
+}
+
+fn list(db: &Database, matches: &ArgMatches, json: bool) {
+    let archive = if matches.is_present("archived") {
+        Archive::Archive
+    } else {
+        Archive::Queue
+    };
+
+    match filter_entries(db, matches.value_of("TAG"), archive) {
+        Ok(entries) if json => print_json(&entries),
+        Ok(entries) if entries.is_empty() => println!("Nothing to list."),
+        Ok(entries) => print_table(&entries),
+        Err(e) => fail(&format!("Couldn't list the links: {}", e)),
+    }
+}
+
+fn search(db: &Database, matches: &ArgMatches, json: bool) {
+    let query = matches.value_of("QUERY").unwrap(); // QUERY is required, so clap ensures it's there
+
+    match search_entries(db, query, matches.value_of("TAG")) {
+        Ok(entries) if json => print_json(&entries),
+        Ok(entries) if entries.is_empty() => println!("No matches for \"{}\".", query),
+        Ok(entries) => print_table(&entries),
+        Err(e) => fail(&format!("Couldn't search the links: {}", e)),
+    }
+}
+
+// The entries matching a search, optionally only those tagged with `tag`.
+fn search_entries(db: &Database, query: &str, tag: Option<&str>) -> error::Result<Vec<OwnedEntry>> {
+    let entries = db.search(query)?;
+    Ok(match tag {
+        Some(tag) => entries
+            .into_iter()
+            .filter(|e| e.tags.iter().any(|t| t.eq_ignore_ascii_case(tag)))
+            .collect(),
+        None => entries,
+    })
+}
+
+fn recent(db: &Database, matches: &ArgMatches, json: bool) {
+    let n = matches
+        .value_of("N")
+        .map(|n| n.parse().unwrap()) // validated by clap
+        .unwrap_or(10);
+
+    match db.list_recent(n) {
+        Ok(entries) if json => print_json(&entries),
+        Ok(entries) if entries.is_empty() => println!("Nothing to list."),
+        Ok(entries) => print_table(&entries),
+        Err(e) => fail(&format!("Couldn't list the links: {}", e)),
+    }
+}
+
+fn untagged(db: &Database, json: bool) {
+    match db.list_untagged() {
+        Ok(entries) if json => print_json(&entries),
+        Ok(entries) if entries.is_empty() => println!("Every link has a tag."),
+        Ok(entries) => print_table(&entries),
+        Err(e) => fail(&format!("Couldn't list the links: {}", e)),
+    }
+}
+
+fn stats(db: &Database, json: bool) {
+    let stats = match db.stats() {
+        Ok(stats) => stats,
+        Err(e) => fail(&format!("Couldn't summarise the pile: {}", e)),
+    };
+    if json {
+        return print_json(&stats);
+    }
+    println!("Entries:    {}", stats.total);
+    println!("  queued:   {}", stats.queued);
+    println!("  archived: {}", stats.archived);
+    println!("Tags:       {}", stats.tags);
+    if let Some(latest) = stats.latest {
+        println!("Last added: {}", latest.format("%Y-%m-%d %H:%M"));
+    }
+}
+
+fn archive_all(db: &Database, matches: &ArgMatches) {
+    let dry_run = matches.is_present("dry_run");
+    if dry_run || !matches.is_present("yes") {
+        let queued = match db.list_entries_by_archive(Archive::Queue) {
+            Ok(queued) => queued,
+            Err(e) => fail(&format!("Couldn't list the pile: {}", e)),
+        };
+        if dry_run {
+            println!("Would move {} links from dump to archive.", queued.len());
+            if !queued.is_empty() {
+                print_table(&queued);
+            }
+            return;
+        }
+        if !confirm(&format!(
+            "Move all {} links in the pile to the archive?",
+            queued.len()
+        )) {
+            return println!("Left the pile alone.");
+        }
+    }
+
+    match db.archive_all() {
+        Ok(n) => println!("Moved {} links from dump to archive.", n),
+        Err(e) => fail(&format!("Couldn't archive the pile: {}", e)),
+    }
+}
+
+fn prune(db: &Database, dry_run: bool) {
+    if dry_run {
+        match db.unused_tags() {
+            Ok(tags) => {
+                println!("Would remove {} unused tags.", tags.len());
+                for tag in tags {
+                    println!("    {}", tag);
+                }
+            }
+            Err(e) => fail(&format!("Couldn't find the unused tags: {}", e)),
+        }
+        return;
+    }
+
+    match db.prune_unused_tags() {
+        Ok(1) => println!("Removed 1 unused tag."),
+        Ok(n) => println!("Removed {} unused tags.", n),
+        Err(e) => fail(&format!("Couldn't prune the tags: {}", e)),
+    }
+}
+
+fn dedupe(db: &Database, dry_run: bool) {
+    if dry_run {
+        match db.find_duplicate_links() {
+            Ok(duplicates) => {
+                println!("Would merge {} duplicated links.", duplicates.len());
+                for (link, ids) in duplicates {
+                    let ids: Vec<String> = ids.iter().map(|i| format!("#{}", i)).collect();
+                    println!("    {} ({})", link, ids.join(", "));
+                }
+            }
+            Err(e) => fail(&format!("Couldn't find the duplicated links: {}", e)),
+        }
+        return;
+    }
+
+    match db.dedupe_links() {
+        Ok(n) => println!("Removed {} duplicate links.", n),
+        Err(e) => fail(&format!("Couldn't merge the duplicated links: {}", e)),
+    }
+}
+
+fn vacuum(db: &Database) {
+    match db.compact() {
+        Ok(()) => println!("Compacted the database."),
+        Err(e) => fail(&format!("Couldn't compact the database: {}", e)),
+    }
+}
+
+fn backup(db: &Database, matches: &ArgMatches) {
+    let path = Path::new(matches.value_of("PATH").unwrap()); // PATH is required, so clap ensures it's there
+    match db.backup(path) {
+        Ok(()) => println!("Backed up the database to {}", path.display()),
+        Err(e) => fail(&format!("Couldn't back up to {}: {}", path.display(), e)),
+    }
+}
+
+// The entries in an archive, optionally only those tagged with `tag`.
+fn filter_entries(
+    db: &Database,
+    tag: Option<&str>,
+    archive: Archive,
+) -> error::Result<Vec<OwnedEntry>> {
+    match tag {
+        Some(tag) => Ok(db
+            .get_links_for_tag(tag)?
+            .into_iter()
+            .filter(|e| e.archive == archive)
+            .collect()),
+        None => db.list_entries_by_archive(archive),
+    }
+}
+
+// Print entries as a table, with a row per entry and the columns aligned.
+fn print_table(entries: &[OwnedEntry]) {
+    let rows: Vec<[String; 4]> = entries
+        .iter()
+        .map(|e| {
+            [
+                format!("#{}", e.id),
+                e.link.clone(),
+                e.comment.clone().unwrap_or_default(),
+                e.tags.join(", "),
+            ]
+        })
+        .collect();
+    let header = [
+        "ID".to_string(),
+        "LINK".to_string(),
+        "COMMENT".to_string(),
+        "TAGS".to_string(),
+    ];
+
+    let mut widths = [0; 4];
+    for row in rows.iter().chain(std::iter::once(&header)) {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+
+    for row in std::iter::once(&header).chain(rows.iter()) {
+        let line = format!(
+            "{:w0$}  {:w1$}  {:w2$}  {}",
+            row[0],
+            row[1],
+            row[2],
+            row[3],
+            w0 = widths[0],
+            w1 = widths[1],
+            w2 = widths[2],
+        );
+        println!("{}", line.trim_end());
+    }
+}
+
+// Print an entry in the same format that we use for listing the pile.
+fn print_entry(entry: &OwnedEntry) {
+    println!("- #{} -- {}", entry.id, entry.link);
+    if let Some(comment) = &entry.comment {
+        println!("    =c= {}", comment);
+    }
+    if !entry.tags.is_empty() {
+        println!("    =t= {}", entry.tags.join(", "));
+    }
+}
+
+// Print a result as JSON, for scripts rather than people.
+fn print_json<T: Serialize + ?Sized>(value: &T) {
+    match serde_json::to_string(value) {
+        Ok(json) => println!("{}", json),
+        Err(e) => fail(&format!("Couldn't write JSON: {}", e)),
+    }
+}
+
+// Ask the user a yes/no question, defaulting to no.
+fn confirm(question: &str) -> bool {
+    matches!(
+        prompt(&format!("{} [y/N]", question)).as_deref(),
+        Some("y") | Some("yes")
+    )
+}
+
+// Ask the user a question, returning their answer trimmed and lowercased, or
+// None if there's no more input.
+fn prompt(question: &str) -> Option<String> {
+    print!("{} ", question);
+    io::stdout().flush().ok()?;
+    let mut answer = String::new();
+    match io::stdin().read_line(&mut answer) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => Some(answer.trim().to_lowercase()),
+    }
+}
+
+// Parse an entry id, as printed by tsd (i.e. with an optional leading '#').
+fn parse_id(id: &str) -> Option<i64> {
+    id.trim_start_matches('#').parse().ok()
+}
+
+// Validator for clap arguments that should be entry ids.
+fn is_id(id: String) -> Result<(), String> {
+    match parse_id(&id) {
+        Some(_) => Ok(()),
+        None => Err(format!(
+            "\"{}\" isn't a valid id, ids look like 42 or #42",
+            id
+        )),
+    }
+}
+
+// Validator for clap arguments that should be a number of things.
+fn is_count(n: String) -> Result<(), String> {
+    match n.parse::<u32>() {
+        Ok(_) => Ok(()),
+        Err(_) => Err(format!("\"{}\" isn't a valid number", n)),
+    }
+}
+
+// Split a comma separated list of tags, ignoring any empty tags.
+fn split_tags(tags: &str) -> Vec<&str> {
+    tags.split(',')
+        .map(|t| t.trim())
+        .filter(|t| !t.is_empty())
+        .collect()
+}
+
+// Add the configured default tags to the ones given on the command line,
+// skipping any that are already there. Tags are case-insensitive, so "Inbox"
+// and "inbox" count as the same tag.
+fn with_default_tags<'a>(mut tags: Vec<&'a str>, default_tags: &'a [String]) -> Vec<&'a str> {
+    for tag in default_tags.iter().map(|t| t.trim()) {
+        if !tag.is_empty() && !tags.iter().any(|t| t.eq_ignore_ascii_case(tag)) {
+            tags.push(tag);
+        }
+    }
+    tags
+}
+
+// Report an error to the user, and bail out.
+fn fail(message: &str) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn add(db: &Database, link: &str, tags: &[&str]) -> i64 {
+        let entry = EntryBuilder::new()
+            .link(link)
+            .tags(tags.iter().cloned())
+            .build()
+            .unwrap();
+        match db.add_entry(entry).unwrap() {
+            EntryAddResult::LinkAlreadyExists => panic!("link should not already exist!"),
+            EntryAddResult::LinkId(i) => i,
+        }
+    }
+
+    #[test]
+    fn open_in_browser_refuses_non_web_links() {
+        let links = [
+            "-x",
+            "--help",
+            "/usr/bin/yes",
+            "C:\\Windows\\System32\\calc.exe",
+            "file:///etc/passwd",
+            "javascript:alert(1)",
+        ];
+        for link in &links {
+            let error = open_in_browser(link).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{}", link);
+        }
+    }
+
+    #[test]
+    fn log_level_from_debug_count() {
+        assert_eq!(log_level(0), LevelFilter::Warn);
+        assert_eq!(log_level(1), LevelFilter::Info);
+        assert_eq!(log_level(2), LevelFilter::Debug);
+        assert_eq!(log_level(3), LevelFilter::Trace);
+        assert_eq!(log_level(10), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn default_tags_merged_without_duplicates() {
+        let defaults = vec!["inbox".to_string(), "Rust".to_string(), " ".to_string()];
+        assert_eq!(
+            with_default_tags(vec!["rust", "programming"], &defaults),
+            vec!["rust", "programming", "inbox"]
+        );
+        assert_eq!(with_default_tags(vec![], &defaults), vec!["inbox", "Rust"]);
+        assert_eq!(with_default_tags(vec!["rust"], &[]), vec!["rust"]);
+    }
+
+    #[test]
+    fn filter_entries_by_tag_and_archive() {
+        let db = Database::open_in_memory().unwrap();
+        let queued_rust = add(&db, "https://www.rust-lang.org", &["rust"]);
+        let read_rust = add(&db, "https://doc.rust-lang.org", &["rust", "docs"]);
+        let queued_go = add(&db, "https://go.dev", &["go"]);
+        db.mark_as_read(read_rust).unwrap();
+
+        let ids = |tag, archive| -> Vec<i64> {
+            filter_entries(&db, tag, archive)
+                .unwrap()
+                .iter()
+                .map(|e| e.id)
+                .collect()
+        };
+        assert_eq!(ids(None, Archive::Queue), vec![queued_rust, queued_go]);
+        assert_eq!(ids(None, Archive::Archive), vec![read_rust]);
+        assert_eq!(ids(Some("rust"), Archive::Queue), vec![queued_rust]);
+        assert_eq!(ids(Some("rust"), Archive::Archive), vec![read_rust]);
+        assert_eq!(ids(Some("docs"), Archive::Queue), Vec::<i64>::new());
+        assert_eq!(ids(Some("go"), Archive::Archive), Vec::<i64>::new());
+        assert_eq!(ids(Some("python"), Archive::Queue), Vec::<i64>::new());
+    }
+}
@@ -1,10 +1,24 @@
 use chrono::naive::NaiveDateTime;
-use rusqlite::NO_PARAMS;
-use rusqlite::{Connection, Result};
+use rusqlite::types::ToSql;
+use rusqlite::{Connection, DatabaseName, Row};
+use serde::{Deserialize, Serialize};
 
-// TODO: Right now, the Tsundoku data model and database (i.e. sqlite)
-// implementation are tied together. They should be separated out and the
-// datamodel refactored into a trait with a separate database implementation.
+use crate::error::{Result, TsundokuError};
+use url::Url;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+
+mod datastore;
+mod export;
+#[cfg(feature = "fetch-title")]
+mod fetch;
+mod import;
+pub use self::datastore::Datastore;
+
+// The Tsundoku data model is described by the `Datastore` trait, and the
+// `Database` below is its sqlite implementation.
 
 //
 // This is the rough overview of what the databse looks like.
@@ -17,7 +31,7 @@ use rusqlite::{Connection, Result};
 // | = comments  : text                |  +--+ = link_id : integer foreign key |
 // | = archive   : integer             |  +--+ = tag_id  : integer foreign key |
 // | = timestamp : text                |  |  |                                 |
-// |                                   |  |  +---------------------------------+
+// | = source    : text                |  |  +---------------------------------+
 // +-----------------------------------+  |
 //                                        |
 // +----------------------------------+   |
@@ -32,18 +46,71 @@ use rusqlite::{Connection, Result};
 // rows in the Links table correspond (roughly) to an "Entry", associated with
 // a particular archive.
 
-/// Archive - a marker of where we "are" in reading a link. Right now, this means it's either in the queue (waiting to be read), or in the Archive (it's been read). This may be expanded to further archives (e.g. "InProgress", "ReReadLater") hence why it is an enum not a bool.
+// Tags are matched ignoring (ASCII) case, so that "Rust" and "rust" are the
+// same tag. Each tag keeps the case it was first added with.
+
+// How timestamps are stored in the database: ISO 8601, with a fixed number of
+// digits in every field, so that sorting them as text sorts them by time.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.9f";
+
+// Read a timestamp back out of the database. Besides our own format, this
+// reads sqlite's own (e.g. from `datetime()`, with a space rather than a "T"),
+// and RFC 3339 timestamps with an offset (converted to UTC), so that
+// timestamps written by hand, or by other tools, can still be read. The
+// fraction of a second can have any number of digits, or none.
+fn parse_timestamp(timestamp: &str) -> Result<NaiveDateTime> {
+    NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%dT%H:%M:%S%.f")
+        .or_else(|_| NaiveDateTime::parse_from_str(timestamp, "%Y-%m-%d %H:%M:%S%.f"))
+        .or_else(|_| chrono::DateTime::parse_from_rfc3339(timestamp).map(|t| t.naive_utc()))
+        .map_err(|_| TsundokuError::BadTimestamp(timestamp.to_string()))
+}
+
+// Steps to bring an older database up to date, in order: `MIGRATIONS[i]`
+// takes the schema from version `i` (as stored in sqlite's `user_version`) to
+// version `i + 1`. Databases from before we kept a version are at version 0.
+// Only ever add new migrations to the end of this list!
+const MIGRATIONS: &[fn(&Connection) -> Result<()>] = &[
+    migrate_archive_and_timestamp,
+    migrate_source,
+    migrate_unique_tags,
+    migrate_unique_linktags,
+];
+
+/// Archive - a marker of where we "are" in reading a link. Either it's in the queue (waiting to be read), in the Archive (it's been read), in progress (we've started reading it), or to be re-read later. The discriminants are how each state is stored in the database, so they must never change.
+#[derive(PartialEq, Eq, Hash, Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Archive {
-    Queue,
-    Archive,
+    #[default]
+    Queue = 0,
+    Archive = 1,
+    InProgress = 2,
+    ReReadLater = 3,
+}
+
+impl TryFrom<u8> for Archive {
+    type Error = u8;
+
+    /// Decode an archive state as stored in the database, giving back the
+    /// value itself if it doesn't correspond to any state.
+    fn try_from(value: u8) -> std::result::Result<Archive, u8> {
+        match value {
+            0 => Ok(Archive::Queue),
+            1 => Ok(Archive::Archive),
+            2 => Ok(Archive::InProgress),
+            3 => Ok(Archive::ReReadLater),
+            _ => Err(value),
+        }
+    }
 }
 
 /// Tag - a sorting/grouping string that can be used to query for specific entries
+#[allow(dead_code)]
 pub struct Tag<'a> {
     detail: &'a str,
 }
 
 /// Comment - A comment on a link, similar to a tag but semanticaly differnt: links/tags are many:many, but links/comments are 1:1
+#[allow(dead_code)]
 pub struct Comment<'a> {
     detail: &'a str,
 }
@@ -55,6 +122,122 @@ pub struct Entry<'a> {
     pub tags: Option<Vec<&'a str>>, // Tags (also optional) for categorising the link
     pub archive: Archive,           // Have we read this link? Do we want to put it somewhere?
     pub timestamp: NaiveDateTime,   // When did we add this link to the database
+    pub source: Option<&'a str>,    // Where the link came from (e.g. "csv"), if we know
+}
+
+/// EntryBuilder - Builds an entry one field at a time, defaulting to an
+/// entry in the queue, timestamped with the time it was built. This is the
+/// easiest way to add an entry "now", without working out the time yourself.
+///
+/// ```
+/// # use tsundoku::datamodel::*;
+/// let entry = EntryBuilder::new()
+///     .link("https://example.com")
+///     .comment("An example")
+///     .tag("tag 0")
+///     .build()
+///     .unwrap();
+/// assert_eq!(entry.tags, Some(vec!["tag 0"]));
+/// assert!(EntryBuilder::new().comment("No link!").build().is_err());
+/// ```
+#[derive(Default)]
+pub struct EntryBuilder<'a> {
+    link: Option<&'a str>,
+    comment: Option<&'a str>,
+    tags: Vec<&'a str>,
+    timestamp: Option<NaiveDateTime>,
+    source: Option<&'a str>,
+}
+
+impl<'a> EntryBuilder<'a> {
+    pub fn new() -> EntryBuilder<'a> {
+        EntryBuilder::default()
+    }
+
+    pub fn link(mut self, link: &'a str) -> EntryBuilder<'a> {
+        self.link = Some(link);
+        self
+    }
+
+    pub fn comment(mut self, comment: &'a str) -> EntryBuilder<'a> {
+        self.comment = Some(comment);
+        self
+    }
+
+    pub fn tag(mut self, tag: &'a str) -> EntryBuilder<'a> {
+        self.tags.push(tag);
+        self
+    }
+
+    pub fn tags<I: IntoIterator<Item = &'a str>>(mut self, tags: I) -> EntryBuilder<'a> {
+        self.tags.extend(tags);
+        self
+    }
+
+    pub fn timestamp(mut self, timestamp: NaiveDateTime) -> EntryBuilder<'a> {
+        self.timestamp = Some(timestamp);
+        self
+    }
+
+    pub fn source(mut self, source: &'a str) -> EntryBuilder<'a> {
+        self.source = Some(source);
+        self
+    }
+
+    /// Build the entry, failing with `TsundokuError::MissingLink` if no link
+    /// was given.
+    pub fn build(self) -> Result<Entry<'a>> {
+        Ok(Entry {
+            link: self.link.ok_or(TsundokuError::MissingLink)?,
+            comment: self.comment,
+            tags: if self.tags.is_empty() {
+                None
+            } else {
+                Some(self.tags)
+            },
+            archive: Archive::Queue,
+            timestamp: self
+                .timestamp
+                .unwrap_or_else(|| chrono::Utc::now().naive_utc()),
+            source: self.source,
+        })
+    }
+}
+
+/// SortOrder - which way round to list entries, by the time they were added
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum SortOrder {
+    NewestFirst,
+    OldestFirst,
+}
+
+/// TagMatch - whether entries need every one of a set of tags, or just one
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum TagMatch {
+    All,
+    Any,
+}
+
+/// OwnedEntry - An entry read back out of the database, which owns its data
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct OwnedEntry {
+    pub id: i64,                  // The link_id of the entry in the database
+    pub link: String,             // Contents of the link
+    pub comment: Option<String>,  // Comment (optional) on the link
+    pub tags: Vec<String>,        // Tags for categorising the link, possibly empty
+    pub archive: Archive,         // Have we read this link? Do we want to put it somewhere?
+    pub timestamp: NaiveDateTime, // When did we add this link to the database
+    pub source: Option<String>,   // Where the link came from (e.g. "csv"), if we know
+}
+
+/// Stats - a summary of what's in the database
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct Stats {
+    pub total: i64,                    // How many entries there are altogether
+    pub queued: i64,                   // How many are waiting in the queue
+    pub archived: i64,                 // How many have been read
+    pub tags: i64,                     // How many tags there are
+    pub latest: Option<NaiveDateTime>, // When the newest entry was added, if there is one
 }
 
 /// The database of links
@@ -74,6 +257,12 @@ pub enum TagQueryResult {
     TagId(i64),
 }
 
+#[derive(PartialEq, Debug)]
+pub enum EntryAddResult {
+    LinkAlreadyExists,
+    LinkId(i64),
+}
+
 impl PartialEq<TagQueryResult> for TagAddResult {
     fn eq(&self, other: &TagQueryResult) -> bool {
         match (self, other) {
@@ -93,15 +282,37 @@ impl PartialEq<TagAddResult> for TagQueryResult {
 
 impl Database {
     pub fn open_in_memory() -> Result<Database> {
-        let conn = Connection::open_in_memory()?;
+        Database::from_connection(Connection::open_in_memory()?)
+    }
+
+    /// Open the database stored in the file at `path`, creating it (and the
+    /// tables we need) if it doesn't exist yet.
+    pub fn open_from_path<P: AsRef<Path>>(path: P) -> Result<Database> {
+        info!("opening database {}", path.as_ref().display());
+        Database::from_connection(Connection::open(path)?)
+    }
 
-        let rows = Database::init_database(&conn)?;
+    /// # Use an existing connection
+    ///
+    /// Creates (or migrates) the tables we need in the database that `conn`
+    /// is connected to, and then uses it as is, e.g. with any pragmas or
+    /// attached databases that it's been set up with.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let conn = rusqlite::Connection::open_in_memory().unwrap();
+    /// let db = Database::from_connection(conn).unwrap();
+    /// assert_eq!(db.count_entries(), Ok(0));
+    /// ```
+    pub fn from_connection(conn: Connection) -> Result<Database> {
+        Database::init_database(&conn)?;
 
-        return Ok(Database { conn: conn });
+        Ok(Database { conn })
     }
 
     fn init_database(conn: &Connection) -> Result<usize> {
-        // Create tables that we need, if they don't already exist.
+        // Create tables that we need, if they don't already exist. These are
+        // the tables as they were before the schema was versioned, and the
+        // migrations below bring them up to date.
         let mut rows = conn.execute(
             "
             create table if not exists links (
@@ -129,155 +340,2716 @@ impl Database {
             )",
             params![],
         )?;
+        // Index the columns we look things up by. Tags are indexed by
+        // `migrate_unique_tags`, as older databases need theirs replacing.
+        rows += conn.execute(
+            "create index if not exists idx_links_link on links(link)",
+            params![],
+        )?;
+        Database::migrate(conn)?;
         Ok(rows)
     }
 
-    /// # Get a tag that exists
+    // Run every migration that the database hasn't had yet, in order,
+    // recording the new version as each one completes.
+    fn migrate(conn: &Connection) -> Result<()> {
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        for (from, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            info!("migrating schema from version {} to {}", from, from + 1);
+            let tx = conn.unchecked_transaction()?;
+            migration(&tx)?;
+            tx.pragma_update(None, "user_version", &(from as i64 + 1))?;
+            tx.commit()?;
+        }
+        Ok(())
+    }
+
+    /// # Find links that have been added more than once
     ///
+    /// Lists each link that's in the database more than once, along with
+    /// the ids of each of its entries, oldest first.
     /// ```
     /// # use tsundoku::datamodel::*;
     /// let db = Database::open_in_memory().unwrap();
-    /// db.add_tag("tag 0");
-    /// db.add_tag("tag 1");
-    /// db.add_tag("tag 2");
-    /// db.add_tag("tag 3");
-    /// db.add_tag("tag 4");
+    /// let entry = || EntryBuilder::new().link("https://example.com").build().unwrap();
+    /// db.add_entry_allow_duplicate(entry()).unwrap();
+    /// assert_eq!(db.find_duplicate_links(), Ok(vec![]));
+    /// db.add_entry_allow_duplicate(entry()).unwrap();
+    /// assert_eq!(
+    ///     db.find_duplicate_links(),
+    ///     Ok(vec![("https://example.com".to_string(), vec![1, 2])])
+    /// );
+    /// ```
+    pub fn find_duplicate_links(&self) -> Result<Vec<(String, Vec<i64>)>> {
+        let mut stmt = self.conn.prepare(
+            "
+            select link, link_id from links
+                where link in (select link from links group by link having count(*) > 1)
+                order by link, link_id",
+        )?;
+        let row_iter = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?;
+
+        let mut duplicates: Vec<(String, Vec<i64>)> = vec![];
+        for row in row_iter {
+            let (link, link_id): (String, i64) = row?;
+            match duplicates.last_mut() {
+                Some((last, ids)) if *last == link => ids.push(link_id),
+                _ => duplicates.push((link, vec![link_id])),
+            }
+        }
+        duplicates.sort_by_key(|(_, ids)| ids[0]);
+        Ok(duplicates)
+    }
+
+    /// # Merge links that have been added more than once
     ///
-    /// let tag_id = db.get_tag_id("tag 3").unwrap();
+    /// For each link that's in the database more than once, the oldest entry
+    /// is kept, with every tag from the other entries added to it, and the
+    /// rest are deleted. The kept entry's comment and archive state are left
+    /// as they are. Returns the number of entries deleted.
+    pub fn dedupe_links(&self) -> Result<usize> {
+        let duplicates = self.find_duplicate_links()?;
+
+        let tx = self.conn.unchecked_transaction()?;
+        let mut removed = 0;
+        for (_, ids) in &duplicates {
+            let (survivor, rest) = ids.split_first().unwrap(); // every link here has at least two ids
+            for link_id in rest {
+                tx.execute(
+                    "
+                    insert into linktags (link_id, tag_id)
+                        select ?1, tag_id from linktags
+                            where link_id == ?2 and tag_id not in (
+                                select tag_id from linktags where link_id == ?1
+                            )",
+                    params![survivor, link_id],
+                )?;
+                tx.execute("delete from linktags where link_id == ?1", params![link_id])?;
+                removed += tx.execute("delete from links where link_id == ?1", params![link_id])?;
+            }
+        }
+        tx.commit()?;
+        debug!("removed {} duplicate links", removed);
+
+        Ok(removed)
+    }
+
+    /// # Delete every entry with a tag
     ///
-    /// assert_eq!(tag_id, TagQueryResult::TagId(4));
+    /// Deletes each entry tagged with `tag`, even if it has other tags too,
+    /// along with all of its tag associations. The tag itself is left in
+    /// place. Returns the number of entries deleted, which is 0 if the tag
+    /// doesn't exist.
     /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let entry = |link, tags| EntryBuilder::new().link(link).tags(tags).build().unwrap();
+    /// db.add_entry(entry("https://tokio.rs", vec!["rust", "async"])).unwrap();
+    /// db.add_entry(entry("https://go.dev", vec!["go"])).unwrap();
+    /// assert_eq!(db.delete_by_tag("rust"), Ok(1));
+    /// assert_eq!(db.count_entries(), Ok(1));
+    /// ```
+    pub fn delete_by_tag(&self, tag: &str) -> Result<usize> {
+        let tag_id = match self.get_tag_id(tag)? {
+            TagQueryResult::TagId(i) => i,
+            TagQueryResult::TagNotFound => return Ok(0),
+        };
+
+        let tx = self.conn.unchecked_transaction()?;
+        let link_ids: Vec<i64> = {
+            let mut stmt = tx.prepare("select link_id from linktags where tag_id == ?1")?;
+            let ids = stmt.query_map(params![tag_id], |row| row.get(0))?;
+            ids.collect::<rusqlite::Result<_>>()?
+        };
+        let mut removed = 0;
+        for link_id in &link_ids {
+            tx.execute("delete from linktags where link_id == ?1", params![link_id])?;
+            removed += tx.execute("delete from links where link_id == ?1", params![link_id])?;
+        }
+        tx.commit()?;
+        info!("deleted {} links tagged {}", removed, tag);
+
+        Ok(removed)
+    }
+
+    /// Compact the database, reclaiming the space left behind by deleted
+    /// entries and tags.
+    pub fn compact(&self) -> Result<()> {
+        Ok(self.conn.execute_batch("vacuum")?)
+    }
+
+    /// Write a consistent copy of the database to the file at `dest`, which
+    /// can be opened with `open_from_path`. This works even if the database
+    /// is in memory, or is being used elsewhere at the same time.
+    pub fn backup<P: AsRef<Path>>(&self, dest: P) -> Result<()> {
+        info!("backing up the database to {}", dest.as_ref().display());
+        Ok(self.conn.backup(DatabaseName::Main, dest, None)?)
+    }
+
+    /// # Add an entry, or merge it into an existing one
     ///
-    /// # Get a tag that doesn't exist
+    /// If the link isn't in the database, this adds the entry just like
+    /// `add_entry`. Otherwise, the entry's tags are added to the existing
+    /// entry (along with its comment, if the existing entry doesn't have
+    /// one), and everything else is left as it was. Returns the id of the
+    /// entry either way.
     /// ```
     /// # use tsundoku::datamodel::*;
     /// let db = Database::open_in_memory().unwrap();
-    /// let tag_id = db.get_tag_id("This tag doesn't exist");
-    /// assert_eq!(tag_id, Ok(TagQueryResult::TagNotFound));
+    /// let entry = |tag| EntryBuilder::new().link("https://example.com").tag(tag).build().unwrap();
+    /// let link_id = db.upsert_entry(entry("tag 0")).unwrap();
+    /// assert_eq!(db.upsert_entry(entry("tag 1")), Ok(link_id));
+    /// assert_eq!(db.get_tags_for_link(link_id).unwrap(), vec!["tag 0", "tag 1"]);
     /// ```
-    pub fn get_tag_id(&self, tag: &str) -> Result<TagQueryResult> {
-        let mut stmt = self
-            .conn
-            .prepare("select tag_id from tags where tag == ?1")?;
-        let mut tag_iter = stmt.query_map(params![tag], |row| Ok(row.get(0)?))?;
-        match tag_iter.next() {
-            Some(Ok(i)) => Ok(TagQueryResult::TagId(i)),
-            Some(Err(e)) => Err(e),
-            None => Ok(TagQueryResult::TagNotFound),
+    pub fn upsert_entry(&self, entry: Entry) -> Result<i64> {
+        let link_id = match self.get_link_id(entry.link)? {
+            Some(i) => i,
+            None => return self.add_entry_allow_duplicate(entry),
+        };
+
+        let tx = self.conn.unchecked_transaction()?;
+        if let Some(comment) = entry.comment.filter(|c| !c.is_empty()) {
+            tx.execute(
+                "update links set comment = ?1 where link_id == ?2 and ifnull(comment, '') == ''",
+                params![comment, link_id],
+            )?;
+        }
+        for tag in entry.tags.unwrap_or_default() {
+            let tag_id = self.get_or_create_tag(tag)?;
+            self.tag_link(tag_id, link_id)?;
         }
+        tx.commit()?;
+        debug!("merged {} into #{}", entry.link, link_id);
+
+        Ok(link_id)
+    }
+
+    /// # Add an entry, checking that its link is a valid URL
+    ///
+    /// Like `add_entry`, but fails with `TsundokuError::InvalidUrl` if the
+    /// link doesn't parse as an http or https URL. If `normalize` is set,
+    /// links without a scheme (e.g. "example.com" or "localhost:3000") are
+    /// given an "https://" one, rather than being rejected.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let entry = |link| EntryBuilder::new().link(link).build().unwrap();
+    /// assert!(db.add_entry_validated(entry("https://example.com"), false).is_ok());
+    /// assert!(db.add_entry_validated(entry("example.org"), false).is_err());
+    /// assert!(db.add_entry_validated(entry("example.org"), true).is_ok());
+    /// assert!(db.contains_link("https://example.org").unwrap());
+    /// ```
+    pub fn add_entry_validated(&self, entry: Entry, normalize: bool) -> Result<EntryAddResult> {
+        let link = validate_url(entry.link, normalize)?;
+        self.add_entry(Entry {
+            link: &link,
+            ..entry
+        })
     }
 
-    /// # Test if a database contains a tag
+    /// Remove a tag from the database, along with every association between
+    /// it and a link. Returns false if the tag doesn't exist.
+    ///
     /// ```
-    /// use tsundoku::datamodel::*;
+    /// # use tsundoku::datamodel::*;
     /// let db = Database::open_in_memory().unwrap();
     /// db.add_tag("tag 0").unwrap();
-    /// let tag_exists = db.contains_tag("tag 0").unwrap();
-    /// assert!(tag_exists);
+    /// assert!(db.remove_tag("tag 0").unwrap());
+    /// assert!(!db.contains_tag("tag 0").unwrap());
+    /// assert!(!db.remove_tag("tag 0").unwrap());
+    /// ```
+    pub fn remove_tag(&self, tag: &str) -> Result<bool> {
+        let tag_id = match self.get_tag_id(tag)? {
+            TagQueryResult::TagId(i) => i,
+            TagQueryResult::TagNotFound => return Ok(false),
+        };
+
+        let tx = self.conn.unchecked_transaction()?;
+        let rows = tx.execute("delete from linktags where tag_id == ?1", params![tag_id])?;
+        tx.execute("delete from tags where tag_id == ?1", params![tag_id])?;
+        tx.commit()?;
+        debug!("removed tag #{} from {} links", tag_id, rows);
+
+        Ok(true)
+    }
+
+    /// # Get a tag's id, adding the tag if it's not there
+    ///
+    /// Returns the id of `tag` (ignoring case) if it's already in the
+    /// database, and otherwise adds it and returns its new id.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let tag_id = db.get_or_create_tag("tag 0").unwrap();
+    /// assert_eq!(db.get_tag_id("tag 0"), Ok(TagQueryResult::TagId(tag_id)));
+    /// assert_eq!(db.get_or_create_tag("Tag 0"), Ok(tag_id));
     /// ```
-    pub fn contains_tag(&self, tag: &str) -> Result<bool> {
-        match self.get_tag_id(tag)? {
-            TagQueryResult::TagId(_) => Ok(true),
-            TagQueryResult::TagNotFound => Ok(false),
+    pub fn get_or_create_tag(&self, tag: &str) -> Result<i64> {
+        match self.conn.query_row(
+            "select tag_id from tags where tag == ?1 collate nocase",
+            params![tag],
+            |row| row.get(0),
+        ) {
+            Ok(tag_id) => Ok(tag_id),
+            Err(rusqlite::Error::QueryReturnedNoRows) => {
+                self.conn.execute(
+                    "insert into tags (tag_id, tag) values (NULL, ?1)",
+                    params![tag],
+                )?;
+                let tag_id = self.conn.last_insert_rowid();
+                debug!("added tag {:?} as #{}", tag, tag_id);
+                Ok(tag_id)
+            }
+            Err(e) => Err(e.into()),
         }
     }
 
-    /// # List tags
+    /// # Tag several entries at once
+    ///
+    /// Tags each of the entries with `tag`, creating it if needed, and
+    /// returns how many entries weren't already tagged with it. Ids that
+    /// don't correspond to an entry are ignored.
     /// ```
     /// # use tsundoku::datamodel::*;
     /// let db = Database::open_in_memory().unwrap();
-    /// let tags: Vec<&str> = vec!["tag 0", "tag 1", "tag 2", "tag 3", "tag 4"];
-    /// for tag in &tags {
-    ///     db.add_tag(tag);
-    /// }
-    /// let db_tags = db.list_tags().unwrap();
-    /// assert_eq!(tags, db_tags);
+    /// let entry = |link| EntryBuilder::new().link(link).build().unwrap();
+    /// db.add_entry(entry("https://example.com")).unwrap();
+    /// db.add_entry(entry("https://example.org")).unwrap();
+    /// assert_eq!(db.tag_links(&[1, 2, 3], "tag 0"), Ok(2));
+    /// assert_eq!(db.tag_links(&[1, 2, 3], "tag 0"), Ok(0));
     /// ```
-    pub fn list_tags(&self) -> Result<Vec<String>> {
-        let mut stmt = self.conn.prepare("select tag from tags;")?;
-        let tag_iter = stmt.query_map(params![], |row| Ok(row.get(0)?))?;
-        tag_iter.collect()
+    pub fn tag_links(&self, link_ids: &[i64], tag: &str) -> Result<usize> {
+        let tx = self.conn.unchecked_transaction()?;
+        let tag_id = self.get_or_create_tag(tag)?;
+
+        let mut tagged = 0;
+        for link_id in link_ids {
+            tagged += self.tag_link(tag_id, *link_id)?;
+        }
+        tx.commit()?;
+        debug!(
+            "tagged {} of {} links with #{}",
+            tagged,
+            link_ids.len(),
+            tag_id
+        );
+
+        Ok(tagged)
     }
 
-    pub fn add_entry(&self, entry: Entry) -> Result<usize> {
-        // set up values for the parameters
-        let link = entry.link;
-        let comment = match entry.comment {
-            Some(c) => c,
-            None => "",
+    /// # Remove a tag from a single entry
+    ///
+    /// Unlike `remove_tag`, the tag itself is left in the database for reuse.
+    /// Returns false if the entry wasn't tagged with `tag`.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let entry = EntryBuilder::new().link("https://example.com").tag("tag 0").build().unwrap();
+    /// db.add_entry(entry).unwrap();
+    /// assert!(db.untag_link(1, "tag 0").unwrap());
+    /// assert!(!db.untag_link(1, "tag 0").unwrap());
+    /// assert!(db.contains_tag("tag 0").unwrap());
+    /// ```
+    pub fn untag_link(&self, link_id: i64, tag: &str) -> Result<bool> {
+        let tag_id = match self.get_tag_id(tag)? {
+            TagQueryResult::TagId(i) => i,
+            TagQueryResult::TagNotFound => return Ok(false),
         };
-        let archive = Archive::Queue as u8; // we *always* add to the queue first
-        let timestamp = entry.timestamp; // convert the timestamp to seconds
 
-        // Add the link itself to the link table
+        let rows = self.conn.execute(
+            "delete from linktags where link_id == ?1 and tag_id == ?2",
+            params![link_id, tag_id],
+        )?;
+        Ok(rows > 0)
+    }
+
+    /// # Rename a tag
+    ///
+    /// Renames the tag in place, so any links tagged with it remain tagged.
+    /// Returns false if the tag doesn't exist, and fails with
+    /// `TsundokuError::DuplicateTag` if a different tag with the new name
+    /// already exists, or `TsundokuError::EmptyTag` if the new name is blank.
+    /// Renaming a tag to itself in a different case is fine.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// db.add_tag("pyton").unwrap();
+    /// assert!(db.rename_tag("pyton", "python").unwrap());
+    /// assert!(db.contains_tag("python").unwrap());
+    /// assert!(!db.contains_tag("pyton").unwrap());
+    /// ```
+    pub fn rename_tag(&self, old: &str, new: &str) -> Result<bool> {
+        if new.trim().is_empty() {
+            return Err(TsundokuError::EmptyTag);
+        }
+        let tag_id = match self.get_tag_id(old)? {
+            TagQueryResult::TagId(i) => i,
+            TagQueryResult::TagNotFound => return Ok(false),
+        };
+        match self.get_tag_id(new)? {
+            TagQueryResult::TagId(i) if i != tag_id => {
+                return Err(TsundokuError::DuplicateTag(new.to_string()))
+            }
+            _ => {}
+        }
+
         self.conn.execute(
+            "update tags set tag = ?1 where tag_id == ?2",
+            params![new, tag_id],
+        )?;
+        Ok(true)
+    }
+
+    /// # Merge one tag into another
+    ///
+    /// Every entry tagged with `source` is tagged with `dest` instead, and
+    /// `source` is removed. Returns false if either tag doesn't exist.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let entry = EntryBuilder::new().link("https://example.com").tag("ml").build().unwrap();
+    /// db.add_entry(entry).unwrap();
+    /// db.add_tag("machine-learning").unwrap();
+    /// assert!(db.merge_tags("ml", "machine-learning").unwrap());
+    /// assert_eq!(db.get_tags_for_link(1).unwrap(), vec!["machine-learning"]);
+    /// assert!(!db.contains_tag("ml").unwrap());
+    /// ```
+    pub fn merge_tags(&self, source: &str, dest: &str) -> Result<bool> {
+        let (source_id, dest_id) = match (self.get_tag_id(source)?, self.get_tag_id(dest)?) {
+            (TagQueryResult::TagId(s), TagQueryResult::TagId(d)) => (s, d),
+            _ => return Ok(false),
+        };
+        if source_id == dest_id {
+            // The same tag (perhaps in a different case), so there's nothing to do
+            return Ok(true);
+        }
+
+        let tx = self.conn.unchecked_transaction()?;
+        // Move links over to the destination, unless they're already tagged
+        // with it, in which case the old association can just go.
+        let moved = tx.execute(
             "
-            insert into links (link_id, link, comment, archive, timestamp)
-                values (null, ?1, ?2, ?3, ?4)
-        ",
-            params![link, comment, archive, timestamp],
+            update linktags set tag_id = ?2
+                where tag_id == ?1 and link_id not in (
+                    select link_id from linktags where tag_id == ?2
+                )",
+            params![source_id, dest_id],
+        )?;
+        tx.execute(
+            "delete from linktags where tag_id == ?1",
+            params![source_id],
+        )?;
+        tx.execute("delete from tags where tag_id == ?1", params![source_id])?;
+        tx.commit()?;
+        debug!(
+            "merged tag #{} into #{}, moving {} links",
+            source_id, dest_id, moved
         );
 
-        // Get the ID of the entry we just pushed
-        let link_id = self.conn.last_insert_rowid();
+        Ok(true)
+    }
 
-        // And iterate through the tags, pushing them to the db.
-        match entry.tags {
-            Some(ts) => {
-                for tag in ts {
-                    self.add_tag(tag);
-                    // also need to link them!
-                }
-            }
-            None => {} //nothing to do
-        };
+    /// # List the tags that aren't used
+    ///
+    /// Lists the tags that no entry is tagged with, in alphabetical order,
+    /// i.e. those that `prune_unused_tags` would remove.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// db.add_entry(EntryBuilder::new().link("https://example.com").tag("used").build().unwrap())
+    ///     .unwrap();
+    /// db.add_tag("unused").unwrap();
+    /// assert_eq!(db.unused_tags().unwrap(), vec!["unused"]);
+    /// ```
+    pub fn unused_tags(&self) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "
+            select tag from tags
+                where tag_id not in (select tag_id from linktags)
+                order by tag collate nocase asc",
+        )?;
+        let tag_iter = stmt.query_map(params![], |row| row.get(0))?;
+        Ok(tag_iter.collect::<rusqlite::Result<_>>()?)
+    }
 
-        Ok(0)
+    /// Remove every tag that no entry is tagged with, returning how many
+    /// were removed.
+    pub fn prune_unused_tags(&self) -> Result<usize> {
+        let rows = self.conn.execute(
+            "delete from tags where tag_id not in (select tag_id from linktags)",
+            params![],
+        )?;
+        debug!("pruned {} unused tags", rows);
+        Ok(rows)
     }
 
-    /// Add a tag to the database. If the tag already exists, this method does nothing.
-    pub fn add_tag(&self, tag: &str) -> Result<TagAddResult> {
-        self.contains_tag(tag).and_then(|contains| {
-            if contains {
-                Ok(TagAddResult::TagAlreadyExists)
-            } else {
-                self.conn
-                    .execute(
-                        "insert into tags (tag_id, tag) values (NULL, ?1)",
-                        params![tag],
-                    )
-                    .map(|_| TagAddResult::TagId(self.conn.last_insert_rowid()))
-            }
-        })
+    /// # Get a tag by id
+    ///
+    /// The reverse of `get_tag_id`, giving `None` if there's no such tag.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// db.add_tag("tag 0").unwrap();
+    /// assert_eq!(db.get_tag_by_id(1), Ok(Some("tag 0".to_string())));
+    /// assert_eq!(db.get_tag_by_id(2), Ok(None));
+    /// ```
+    pub fn get_tag_by_id(&self, tag_id: i64) -> Result<Option<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("select tag from tags where tag_id == ?1")?;
+        let mut tag_iter = stmt.query_map(params![tag_id], |row| row.get(0))?;
+        Ok(tag_iter.next().transpose()?)
     }
 
-    fn tag_link(&self, tag_id: i64, link_id: i64) {}
-}
+    /// # Find tags by prefix
+    ///
+    /// Lists the tags starting with `prefix` (ignoring case) in alphabetical
+    /// order, e.g. for completing tags in a shell. An empty prefix lists
+    /// every tag.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// for tag in &["rustacean", "python", "rust"] {
+    ///     db.add_tag(tag).unwrap();
+    /// }
+    /// assert_eq!(db.tags_with_prefix("rust").unwrap(), vec!["rust", "rustacean"]);
+    /// ```
+    pub fn tags_with_prefix(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "
+            select tag from tags
+                where tag like ?1 || '%' escape '\\'
+                order by tag collate nocase",
+        )?;
+        let tag_iter = stmt.query_map(params![escape_like(prefix)], |row| row.get(0))?;
+        Ok(tag_iter.collect::<rusqlite::Result<_>>()?)
+    }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    // Tests for private members.
-    #[test]
-    fn add_tag_get_tag_same() {
-        let db = Database::open_in_memory().unwrap();
-        // Add some tags so that we don't just have zero
-        let tags: Vec<&str> = vec!["tag 0", "tag 1", "tag 2", "tag 3", "tag 4"];
+    /// List every tag, along with the number of entries tagged with it. The
+    /// most used tags come first, and tags used equally are sorted by name.
+    pub fn list_tags_with_counts(&self) -> Result<Vec<(String, i64)>> {
+        let mut stmt = self.conn.prepare(
+            "
+            select tags.tag, count(linktags.link_id) as uses from tags
+                left join linktags on tags.tag_id == linktags.tag_id
+                group by tags.tag_id
+                order by uses desc, tags.tag collate nocase asc",
+        )?;
+        let tag_iter = stmt.query_map(params![], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        Ok(tag_iter.collect::<rusqlite::Result<_>>()?)
+    }
 
-        for tag in &tags {
-            db.add_tag(tag).unwrap();
-        }
-        // Add a tag and get the id from that
-        let add_id = match db.add_tag("test tag").unwrap() {
-            TagAddResult::TagAlreadyExists => panic!("tag should not already exist!"),
-            TagAddResult::TagId(i) => i,
-        };
-        // Query for the tag, and get the id
-        let query_id = match db.get_tag_id("test tag").unwrap() {
-            TagQueryResult::TagNotFound => panic!("Tag should exist and show up in query!)"),
-            TagQueryResult::TagId(i) => i,
-        };
+    /// Change the comment on an entry (or clear it, with `None`), returning
+    /// false if there's no entry with the given id.
+    pub fn update_comment(&self, link_id: i64, comment: Option<&str>) -> Result<bool> {
+        let rows = self.conn.execute(
+            "update links set comment = ?1 where link_id == ?2",
+            params![comment.unwrap_or_default(), link_id],
+        )?;
+        Ok(rows > 0)
+    }
 
-        assert_eq!(add_id, query_id);
+    /// Move an entry to the given archive, returning false if there's no
+    /// entry with the given id.
+    pub fn set_archive(&self, link_id: i64, archive: Archive) -> Result<bool> {
+        let rows = self.conn.execute(
+            "update links set archive = ?1 where link_id == ?2",
+            params![archive as u8, link_id],
+        )?;
+        debug!("moved #{} to {:?} ({} rows)", link_id, archive, rows);
+        Ok(rows > 0)
+    }
+
+    /// # Mark an entry as read
+    ///
+    /// Moves the entry from the queue to the archive, returning false if
+    /// there's no entry with the given id.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let link_id = db
+    ///     .add_entry_allow_duplicate(Entry {
+    ///         link: "https://example.com",
+    ///         comment: None,
+    ///         tags: None,
+    ///         archive: Archive::Queue,
+    ///         timestamp: chrono::Utc::now().naive_utc(),
+    ///         source: None,
+    ///     })
+    ///     .unwrap();
+    /// assert!(db.mark_as_read(link_id).unwrap());
+    /// assert_eq!(db.get_entry(link_id).unwrap().unwrap().archive, Archive::Archive);
+    /// assert!(!db.mark_as_read(link_id + 1).unwrap());
+    /// ```
+    pub fn mark_as_read(&self, link_id: i64) -> Result<bool> {
+        self.set_archive(link_id, Archive::Archive)
+    }
+
+    /// Move every entry in the queue to the archive, returning how many were
+    /// moved. Entries in any other state are left where they are.
+    pub fn archive_all(&self) -> Result<usize> {
+        let rows = self.conn.execute(
+            "update links set archive = ?1 where archive == ?2",
+            params![Archive::Archive as u8, Archive::Queue as u8],
+        )?;
+        debug!("archived {} queued links", rows);
+        Ok(rows)
+    }
+
+    /// Move an entry (back) into the queue, e.g. to read it again, returning
+    /// false if there's no entry with the given id.
+    pub fn requeue(&self, link_id: i64) -> Result<bool> {
+        self.set_archive(link_id, Archive::Queue)
+    }
+
+    /// List every entry in the database, in the order they were added.
+    pub fn list_entries(&self) -> Result<Vec<OwnedEntry>> {
+        self.query_entries("order by link_id", params![])
+    }
+
+    /// List the entries in the given archive, in the order they were added.
+    pub fn list_entries_by_archive(&self, state: Archive) -> Result<Vec<OwnedEntry>> {
+        self.query_entries("where archive == ?1 order by link_id", params![state as u8])
+    }
+
+    /// List every entry in the database, sorted by when they were added.
+    /// Entries added at the same time are listed in the order they were added.
+    pub fn list_entries_sorted(&self, order: SortOrder) -> Result<Vec<OwnedEntry>> {
+        self.query_entries(
+            match order {
+                SortOrder::NewestFirst => "order by timestamp desc, link_id desc",
+                SortOrder::OldestFirst => "order by timestamp asc, link_id asc",
+            },
+            params![],
+        )
+    }
+
+    /// List the `n` most recently added entries, newest first, whether
+    /// they've been read or not.
+    pub fn list_recent(&self, n: u32) -> Result<Vec<OwnedEntry>> {
+        self.query_entries("order by timestamp desc, link_id desc limit ?1", params![n])
+    }
+
+    /// List the entries that don't have any tags, in the order they were
+    /// added, whether they've been read or not.
+    pub fn list_untagged(&self) -> Result<Vec<OwnedEntry>> {
+        self.query_entries(
+            "where link_id not in (select link_id from linktags) order by link_id",
+            params![],
+        )
+    }
+
+    /// List a page of at most `limit` entries, skipping the first `offset`
+    /// entries, in the same order as `list_entries`.
+    pub fn list_entries_paged(&self, limit: u32, offset: u32) -> Result<Vec<OwnedEntry>> {
+        self.query_entries(
+            "order by link_id limit ?1 offset ?2",
+            params![limit, offset],
+        )
+    }
+
+    /// Count the entries in the database.
+    pub fn count_entries(&self) -> Result<i64> {
+        Ok(self
+            .conn
+            .query_row("select count(*) from links", params![], |row| row.get(0))?)
+    }
+
+    /// # Count the entries in each archive state
+    ///
+    /// States without any entries are left out, rather than counted as zero.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// db.add_entry(EntryBuilder::new().link("https://example.com").build().unwrap()).unwrap();
+    /// let counts = db.counts_by_archive().unwrap();
+    /// assert_eq!(counts.get(&Archive::Queue), Some(&1));
+    /// assert_eq!(counts.get(&Archive::Archive), None);
+    /// ```
+    pub fn counts_by_archive(&self) -> Result<HashMap<Archive, i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("select archive, count(*) from links group by archive")?;
+        let counts = stmt.query_map(params![], |row| {
+            let archive = Archive::try_from(row.get::<_, u8>(0)?)
+                .map_err(|i| rusqlite::Error::IntegralValueOutOfRange(0, i64::from(i)))?;
+            Ok((archive, row.get(1)?))
+        })?;
+        Ok(counts.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// # Summarise the database
+    ///
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let stats = db.stats().unwrap();
+    /// assert_eq!(stats.total, 0);
+    /// assert_eq!(stats.latest, None);
+    /// ```
+    pub fn stats(&self) -> Result<Stats> {
+        let (stats, latest) = self.conn.query_row(
+            "
+            select
+                (select count(*) from links),
+                (select count(*) from links where archive == ?1),
+                (select count(*) from links where archive == ?2),
+                (select count(*) from tags),
+                (select max(timestamp) from links)",
+            params![Archive::Queue as u8, Archive::Archive as u8],
+            |row| {
+                let stats = Stats {
+                    total: row.get(0)?,
+                    queued: row.get(1)?,
+                    archived: row.get(2)?,
+                    tags: row.get(3)?,
+                    latest: None,
+                };
+                Ok((stats, row.get::<_, Option<String>>(4)?))
+            },
+        )?;
+        Ok(Stats {
+            latest: latest.as_deref().map(parse_timestamp).transpose()?,
+            ..stats
+        })
+    }
+
+    /// # Get the entries tagged with a tag
+    ///
+    /// Finds all entries tagged with `tag`, in the order they were added.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// db.add_entry(Entry {
+    ///     link: "https://example.com",
+    ///     comment: None,
+    ///     tags: Some(vec!["tag 0", "tag 1"]),
+    ///     archive: Archive::Queue,
+    ///     timestamp: chrono::Utc::now().naive_utc(),
+    ///     source: None,
+    /// })
+    /// .unwrap();
+    /// assert_eq!(db.get_links_for_tag("tag 1").unwrap().len(), 1);
+    /// assert_eq!(db.get_links_for_tag("tag 2").unwrap().len(), 0);
+    /// ```
+    pub fn get_links_for_tag(&self, tag: &str) -> Result<Vec<OwnedEntry>> {
+        self.query_entries(
+            "
+            where link_id in (
+                select linktags.link_id from linktags
+                    inner join tags on tags.tag_id == linktags.tag_id
+                    where tags.tag == ?1 collate nocase
+            )
+            order by link_id",
+            params![tag],
+        )
+    }
+
+    /// Count the entries tagged with `tag`, without reading them, giving 0
+    /// if the tag doesn't exist.
+    pub fn count_links_for_tag(&self, tag: &str) -> Result<i64> {
+        let tag_id = match self.get_tag_id(tag)? {
+            TagQueryResult::TagId(i) => i,
+            TagQueryResult::TagNotFound => return Ok(0),
+        };
+        Ok(self.conn.query_row(
+            "select count(*) from linktags where tag_id == ?1",
+            params![tag_id],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// Is anything in the queue tagged with `tag`? This is false if the tag
+    /// doesn't exist.
+    pub fn has_queued_with_tag(&self, tag: &str) -> Result<bool> {
+        Ok(self.conn.query_row(
+            "
+            select exists (
+                select 1 from links
+                    inner join linktags on linktags.link_id == links.link_id
+                    inner join tags on tags.tag_id == linktags.tag_id
+                    where links.archive == ?1 and tags.tag == ?2 collate nocase
+            )",
+            params![Archive::Queue as u8, tag],
+            |row| row.get(0),
+        )?)
+    }
+
+    /// # Get the entries tagged with several tags
+    ///
+    /// Finds the entries tagged with every one of `tags` (`TagMatch::All`),
+    /// or with at least one of them (`TagMatch::Any`), in the order they were
+    /// added.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let entry = |link, tags| EntryBuilder::new().link(link).tags(tags).build().unwrap();
+    /// db.add_entry(entry("https://tokio.rs", vec!["rust", "async"])).unwrap();
+    /// db.add_entry(entry("https://go.dev", vec!["go"])).unwrap();
+    /// assert_eq!(db.get_links_for_tags(&["rust", "async"], TagMatch::All).unwrap().len(), 1);
+    /// assert_eq!(db.get_links_for_tags(&["rust", "go"], TagMatch::All).unwrap().len(), 0);
+    /// assert_eq!(db.get_links_for_tags(&["rust", "go"], TagMatch::Any).unwrap().len(), 2);
+    /// ```
+    pub fn get_links_for_tags(&self, tags: &[&str], mode: TagMatch) -> Result<Vec<OwnedEntry>> {
+        let mut tags = tags.to_vec();
+        tags.sort_unstable_by_key(|t| t.to_ascii_lowercase());
+        tags.dedup_by(|a, b| a.eq_ignore_ascii_case(b));
+        if tags.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let placeholders: Vec<String> = (1..=tags.len()).map(|i| format!("?{}", i)).collect();
+        let tag_count = tags.len() as i64;
+        let mut params: Vec<&dyn ToSql> = tags.iter().map(|t| t as &dyn ToSql).collect();
+        let having = match mode {
+            TagMatch::All => {
+                params.push(&tag_count);
+                format!("having count(distinct tags.tag_id) == ?{}", params.len())
+            }
+            TagMatch::Any => String::new(),
+        };
+
+        self.query_entries(
+            &format!(
+                "
+                where link_id in (
+                    select linktags.link_id from linktags
+                        inner join tags on tags.tag_id == linktags.tag_id
+                        where tags.tag collate nocase in ({})
+                        group by linktags.link_id
+                        {}
+                )
+                order by link_id",
+                placeholders.join(", "),
+                having
+            ),
+            &params,
+        )
+    }
+
+    /// Get the oldest entry in the queue, if there's anything in it. Entries
+    /// added at the same time come out in the order they were added.
+    pub fn next_queued(&self) -> Result<Option<OwnedEntry>> {
+        let mut entries = self.query_entries(
+            "where archive == ?1 order by timestamp asc, link_id asc limit 1",
+            params![Archive::Queue as u8],
+        )?;
+        Ok(entries.pop())
+    }
+
+    /// Pick a random entry from the queue, if there's anything in it.
+    pub fn random_queued(&self) -> Result<Option<OwnedEntry>> {
+        self.random_queued_excluding(&[])
+    }
+
+    /// # Pick a random entry from the queue, skipping some
+    ///
+    /// Like `random_queued`, but never picks any of the entries in `exclude`,
+    /// e.g. ones that have already been suggested.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let entry = |link| EntryBuilder::new().link(link).build().unwrap();
+    /// db.add_entry(entry("https://example.com")).unwrap();
+    /// db.add_entry(entry("https://example.org")).unwrap();
+    /// assert_eq!(db.random_queued_excluding(&[1]).unwrap().unwrap().id, 2);
+    /// assert_eq!(db.random_queued_excluding(&[1, 2]), Ok(None));
+    /// ```
+    pub fn random_queued_excluding(&self, exclude: &[i64]) -> Result<Option<OwnedEntry>> {
+        let queued = Archive::Queue as u8;
+        let mut params: Vec<&dyn ToSql> = vec![&queued];
+        params.extend(exclude.iter().map(|i| i as &dyn ToSql));
+        let excluding = if exclude.is_empty() {
+            String::new()
+        } else {
+            let placeholders: Vec<String> = (2..=params.len()).map(|i| format!("?{}", i)).collect();
+            format!("and link_id not in ({})", placeholders.join(", "))
+        };
+
+        let mut entries = self.query_entries(
+            &format!(
+                "where archive == ?1 {} order by random() limit 1",
+                excluding
+            ),
+            &params,
+        )?;
+        Ok(entries.pop())
+    }
+
+    /// Pick a random entry from the queue that's tagged with `tag`, if there
+    /// are any.
+    pub fn random_queued_for_tag(&self, tag: &str) -> Result<Option<OwnedEntry>> {
+        let mut entries = self.query_entries(
+            "
+            where archive == ?1 and link_id in (
+                select linktags.link_id from linktags
+                    inner join tags on tags.tag_id == linktags.tag_id
+                    where tags.tag == ?2 collate nocase
+            )
+            order by random() limit 1",
+            params![Archive::Queue as u8, tag],
+        )?;
+        Ok(entries.pop())
+    }
+
+    /// # Search entries
+    ///
+    /// Finds entries whose link or comment contains the query, ignoring case.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// db.add_entry(Entry {
+    ///     link: "https://example.com",
+    ///     comment: Some("An Example"),
+    ///     tags: None,
+    ///     archive: Archive::Queue,
+    ///     timestamp: chrono::Utc::now().naive_utc(),
+    ///     source: None,
+    /// })
+    /// .unwrap();
+    /// assert_eq!(db.search("example").unwrap().len(), 1);
+    /// assert_eq!(db.search("100%").unwrap().len(), 0);
+    /// ```
+    pub fn search(&self, query: &str) -> Result<Vec<OwnedEntry>> {
+        self.query_entries(
+            "
+            where link like '%' || ?1 || '%' escape '\\'
+                or comment like '%' || ?1 || '%' escape '\\'
+                order by link_id",
+            params![escape_like(query)],
+        )
+    }
+
+    /// # Visit every entry
+    ///
+    /// Calls `f` with each entry in turn, in the order they were added,
+    /// reading them from the database one at a time rather than all at once.
+    /// Stops at the first error returned by `f`.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// db.add_entry(EntryBuilder::new().link("https://example.com").build().unwrap()).unwrap();
+    /// let mut links = vec![];
+    /// db.for_each_entry(|entry| {
+    ///     links.push(entry.link);
+    ///     Ok(())
+    /// })
+    /// .unwrap();
+    /// assert_eq!(links, vec!["https://example.com"]);
+    /// ```
+    pub fn for_each_entry<F: FnMut(OwnedEntry) -> Result<()>>(&self, f: F) -> Result<()> {
+        self.visit_entries("order by link_id", params![], f)
+    }
+
+    // Select entries from the links table, filtered/ordered by the given
+    // clause, and pull in the tags for each.
+    fn query_entries(&self, clause: &str, params: &[&dyn ToSql]) -> Result<Vec<OwnedEntry>> {
+        let mut entries = vec![];
+        self.visit_entries(clause, params, |entry| {
+            entries.push(entry);
+            Ok(())
+        })?;
+        Ok(entries)
+    }
+
+    // Like `query_entries`, but hand each entry to `f` as it's read, rather
+    // than collecting them.
+    fn visit_entries<F: FnMut(OwnedEntry) -> Result<()>>(
+        &self,
+        clause: &str,
+        params: &[&dyn ToSql],
+        mut f: F,
+    ) -> Result<()> {
+        let sql = format!(
+            "select link_id, link, comment, archive, timestamp, source from links {}",
+            clause
+        );
+        trace!("querying entries: {}", sql);
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query(params)?;
+        while let Some(row) = rows.next()? {
+            let mut entry = Database::entry_from_row(row)?;
+            entry.tags = self.get_tags_for_link(entry.id)?;
+            f(entry)?;
+        }
+        Ok(())
+    }
+
+    // Decode a row (as selected by `query_entries`) into an entry, without tags.
+    fn entry_from_row(row: &Row) -> Result<OwnedEntry> {
+        let comment: Option<String> = row.get(2)?;
+        let archive = Archive::try_from(row.get::<_, u8>(3)?)
+            .map_err(|i| rusqlite::Error::IntegralValueOutOfRange(3, i64::from(i)))?;
+        Ok(OwnedEntry {
+            id: row.get(0)?,
+            link: row.get(1)?,
+            comment: comment.filter(|c| !c.is_empty()),
+            tags: vec![],
+            archive,
+            timestamp: parse_timestamp(&row.get::<_, String>(4)?)?,
+            source: row.get(5)?,
+        })
+    }
+
+    // Tag a link, if the link exists and isn't already tagged, returning the
+    // number of associations added.
+    fn tag_link(&self, tag_id: i64, link_id: i64) -> Result<usize> {
+        Ok(self.conn.execute(
+            "
+            insert into linktags (link_id, tag_id)
+                select link_id, ?2 from links
+                    where link_id == ?1 and not exists (
+                        select 1 from linktags where link_id == ?1 and tag_id == ?2
+                    )",
+            params![link_id, tag_id],
+        )?)
+    }
+}
+
+impl Datastore for Database {
+    /// # Get a tag that exists
+    ///
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// db.add_tag("tag 0");
+    /// db.add_tag("tag 1");
+    /// db.add_tag("tag 2");
+    /// db.add_tag("tag 3");
+    /// db.add_tag("tag 4");
+    ///
+    /// let tag_id = db.get_tag_id("tag 3").unwrap();
+    ///
+    /// assert_eq!(tag_id, TagQueryResult::TagId(4));
+    /// ```
+    ///
+    /// # Get a tag that doesn't exist
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let tag_id = db.get_tag_id("This tag doesn't exist");
+    /// assert_eq!(tag_id, Ok(TagQueryResult::TagNotFound));
+    /// ```
+    fn get_tag_id(&self, tag: &str) -> Result<TagQueryResult> {
+        let mut stmt = self
+            .conn
+            .prepare("select tag_id from tags where tag == ?1 collate nocase")?;
+        let mut tag_iter = stmt.query_map(params![tag], |row| row.get(0))?;
+        match tag_iter.next() {
+            Some(Ok(i)) => Ok(TagQueryResult::TagId(i)),
+            Some(Err(e)) => Err(e.into()),
+            None => Ok(TagQueryResult::TagNotFound),
+        }
+    }
+
+    /// # List tags
+    ///
+    /// Tags are listed in alphabetical order, ignoring case.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// for tag in &["tag 3", "tag 0", "tag 4", "tag 2", "tag 1"] {
+    ///     db.add_tag(tag);
+    /// }
+    /// let db_tags = db.list_tags().unwrap();
+    /// assert_eq!(db_tags, vec!["tag 0", "tag 1", "tag 2", "tag 3", "tag 4"]);
+    /// ```
+    fn list_tags(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .conn
+            .prepare("select tag from tags order by tag collate nocase asc")?;
+        let tag_iter = stmt.query_map(params![], |row| row.get(0))?;
+        Ok(tag_iter.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// # Get the id of a link
+    ///
+    /// If the link has been added more than once, the first id is returned.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// assert_eq!(db.get_link_id("https://example.com"), Ok(None));
+    /// ```
+    fn get_link_id(&self, link: &str) -> Result<Option<i64>> {
+        let mut stmt = self
+            .conn
+            .prepare("select link_id from links where link == ?1 order by link_id")?;
+        let mut link_iter = stmt.query_map(params![link], |row| row.get(0))?;
+        Ok(link_iter.next().transpose()?)
+    }
+
+    /// Add an entry to the database, even if the link is already in the
+    /// database. Returns the id of the new link. Either the whole entry is
+    /// added, along with all of its tags, or (if anything fails) none of it.
+    /// The entry keeps its archive state, so links that have already been
+    /// read can go straight into the archive.
+    fn add_entry_allow_duplicate(&self, entry: Entry) -> Result<i64> {
+        // Everything below happens in one transaction, which is rolled back
+        // when it's dropped if we return early with an error.
+        let tx = self.conn.unchecked_transaction()?;
+
+        // set up values for the parameters
+        let link = entry.link;
+        let comment = entry.comment.unwrap_or_default();
+        let archive = entry.archive as u8;
+        let timestamp = entry.timestamp.format(TIMESTAMP_FORMAT).to_string();
+        let source = entry.source;
+
+        // Add the link itself to the link table
+        self.conn.execute(
+            "
+            insert into links (link_id, link, comment, archive, timestamp, source)
+                values (null, ?1, ?2, ?3, ?4, ?5)
+        ",
+            params![link, comment, archive, timestamp, source],
+        )?;
+
+        // Get the ID of the entry we just pushed
+        let link_id = self.conn.last_insert_rowid();
+
+        // And iterate through the tags, pushing them to the db, and linking them.
+        if let Some(ts) = entry.tags {
+            for tag in ts {
+                let tag_id = self.get_or_create_tag(tag)?;
+                self.tag_link(tag_id, link_id)?;
+            }
+        }
+
+        tx.commit()?;
+        debug!("added {} as #{}", link, link_id);
+        Ok(link_id)
+    }
+
+    /// Add a tag to the database. If the tag already exists, this method does nothing.
+    fn add_tag(&self, tag: &str) -> Result<TagAddResult> {
+        self.contains_tag(tag).and_then(|contains| {
+            if contains {
+                Ok(TagAddResult::TagAlreadyExists)
+            } else {
+                self.conn
+                    .execute(
+                        "insert into tags (tag_id, tag) values (NULL, ?1)",
+                        params![tag],
+                    )
+                    .map(|_| {
+                        let tag_id = self.conn.last_insert_rowid();
+                        debug!("added tag {:?} as #{}", tag, tag_id);
+                        TagAddResult::TagId(tag_id)
+                    })
+                    .map_err(TsundokuError::from)
+            }
+        })
+    }
+
+    /// # Get an entry by id
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let link_id = db
+    ///     .add_entry_allow_duplicate(Entry {
+    ///         link: "https://example.com",
+    ///         comment: Some("An example"),
+    ///         tags: Some(vec!["tag 0"]),
+    ///         archive: Archive::Queue,
+    ///         timestamp: chrono::Utc::now().naive_utc(),
+    ///         source: None,
+    ///     })
+    ///     .unwrap();
+    /// let entry = db.get_entry(link_id).unwrap().unwrap();
+    /// assert_eq!(entry.link, "https://example.com");
+    /// assert_eq!(entry.comment, Some("An example".to_string()));
+    /// assert_eq!(entry.tags, vec!["tag 0"]);
+    /// assert_eq!(db.get_entry(link_id + 1), Ok(None));
+    /// ```
+    fn get_entry(&self, link_id: i64) -> Result<Option<OwnedEntry>> {
+        let mut entries = self.query_entries("where link_id == ?1", params![link_id])?;
+        Ok(entries.pop())
+    }
+
+    /// Get the tags associated with a link, in the order they were added to the database.
+    fn get_tags_for_link(&self, link_id: i64) -> Result<Vec<String>> {
+        let mut stmt = self.conn.prepare(
+            "
+            select tags.tag from tags
+                inner join linktags on tags.tag_id == linktags.tag_id
+                where linktags.link_id == ?1
+                order by tags.tag_id",
+        )?;
+        let tag_iter = stmt.query_map(params![link_id], |row| row.get(0))?;
+        Ok(tag_iter.collect::<rusqlite::Result<_>>()?)
+    }
+
+    /// Delete an entry from the database, along with its tag associations.
+    /// Tags themselves are left in place, even if no other entry uses them.
+    /// Returns false if there's no entry with the given id.
+    fn delete_entry(&self, link_id: i64) -> Result<bool> {
+        let tx = self.conn.unchecked_transaction()?;
+        tx.execute("delete from linktags where link_id == ?1", params![link_id])?;
+        let rows = tx.execute("delete from links where link_id == ?1", params![link_id])?;
+        tx.commit()?;
+        debug!("deleted #{} ({} rows)", link_id, rows);
+
+        Ok(rows > 0)
+    }
+}
+
+// Version 1: track where each link is in the reading process, and when it was
+// added. We don't know when existing links were added, so they get stamped
+// with the time of the migration.
+fn migrate_archive_and_timestamp(conn: &Connection) -> Result<()> {
+    if !has_column(conn, "links", "archive")? {
+        conn.execute(
+            "alter table links add column archive INTEGER NOT NULL DEFAULT 0",
+            params![],
+        )?;
+    }
+    if !has_column(conn, "links", "timestamp")? {
+        conn.execute("alter table links add column timestamp TEXT", params![])?;
+    }
+    let now = chrono::Utc::now()
+        .naive_utc()
+        .format(TIMESTAMP_FORMAT)
+        .to_string();
+    conn.execute(
+        "update links set timestamp = ?1 where timestamp is null",
+        params![now],
+    )?;
+    Ok(())
+}
+
+// Version 2: remember where each link came from, e.g. which kind of import.
+// We don't know for existing links, so they're left without a source.
+fn migrate_source(conn: &Connection) -> Result<()> {
+    if !has_column(conn, "links", "source")? {
+        conn.execute("alter table links add column source TEXT", params![])?;
+    }
+    Ok(())
+}
+
+// Version 3: make tags unique ignoring case, so that the database itself
+// makes sure that a tag is never added twice. Older databases may already
+// have tags differing only in case, so those are merged into the first one
+// added, before the unique index replaces the old ones.
+fn migrate_unique_tags(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        update linktags set tag_id = (
+            select min(first.tag_id) from tags as first, tags as this
+                where this.tag_id == linktags.tag_id
+                    and first.tag == this.tag collate nocase
+        );
+        delete from linktags where rowid not in (
+            select min(rowid) from linktags group by link_id, tag_id
+        );
+        delete from tags where tag_id not in (
+            select min(tag_id) from tags group by tag collate nocase
+        );
+        drop index if exists idx_tags_tag;
+        drop index if exists idx_tags_tag_nocase;
+        create unique index idx_tags_tag_nocase on tags(tag collate nocase);",
+    )?;
+    Ok(())
+}
+
+// Version 4: make sure that a link is only tagged with each tag once. Older
+// databases could tag a link with the same tag twice, if it was given in
+// different cases, so the repeats are removed first.
+fn migrate_unique_linktags(conn: &Connection) -> Result<()> {
+    conn.execute_batch(
+        "
+        delete from linktags where rowid not in (
+            select min(rowid) from linktags group by link_id, tag_id
+        );
+        create unique index idx_linktags_link_tag on linktags(link_id, tag_id);",
+    )?;
+    Ok(())
+}
+
+// Does `table` have a column named `column`? Some databases were created with
+// only part of a later schema, so migrations need to check.
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("pragma table_info({})", table))?;
+    let names = stmt.query_map(params![], |row| row.get::<_, String>(1))?;
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// Check that a link is a valid http(s) URL, optionally adding an https scheme
+// to links that don't have one, and return the (possibly normalized) link.
+// Something like "localhost:3000" parses as a URL with a "localhost" scheme,
+// so it counts as not having a scheme, rather than as a URL we don't accept.
+fn validate_url(link: &str, normalize: bool) -> Result<String> {
+    let invalid = || TsundokuError::InvalidUrl(link.to_string());
+    let link = link.trim();
+    if link.is_empty() {
+        return Err(invalid());
+    }
+    let is_web = |url: &Url| url.scheme() == "http" || url.scheme() == "https";
+    match Url::parse(link) {
+        Ok(url) if is_web(&url) => Ok(link.to_string()),
+        Ok(_) | Err(url::ParseError::RelativeUrlWithoutBase)
+            if normalize && !link.contains("://") =>
+        {
+            let normalized = format!("https://{}", link);
+            match Url::parse(&normalized) {
+                Ok(url) if url.host().is_some() => Ok(normalized),
+                _ => Err(invalid()),
+            }
+        }
+        _ => Err(invalid()),
+    }
+}
+
+// Escape the wildcards in a string that's going to be used in a `LIKE`
+// pattern, using backslash as the escape character.
+fn escape_like(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if c == '\\' || c == '%' || c == '_' {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // A queued entry with no comment, timestamped now.
+    fn entry<'a>(link: &'a str, tags: &[&'a str]) -> Entry<'a> {
+        Entry {
+            link,
+            comment: None,
+            tags: Some(tags.to_vec()),
+            archive: Archive::Queue,
+            timestamp: chrono::Utc::now().naive_utc(),
+            source: None,
+        }
+    }
+
+    // Add an entry that should be new to the database, and get its id.
+    fn add(db: &Database, entry: Entry) -> i64 {
+        match db.add_entry(entry).unwrap() {
+            EntryAddResult::LinkAlreadyExists => panic!("link should not already exist!"),
+            EntryAddResult::LinkId(i) => i,
+        }
+    }
+
+    // Tests for private members.
+    #[test]
+    fn add_tag_get_tag_same() {
+        let db = Database::open_in_memory().unwrap();
+        // Add some tags so that we don't just have zero
+        let tags: Vec<&str> = vec!["tag 0", "tag 1", "tag 2", "tag 3", "tag 4"];
+
+        for tag in &tags {
+            db.add_tag(tag).unwrap();
+        }
+        // Add a tag and get the id from that
+        let add_id = match db.add_tag("test tag").unwrap() {
+            TagAddResult::TagAlreadyExists => panic!("tag should not already exist!"),
+            TagAddResult::TagId(i) => i,
+        };
+        // Query for the tag, and get the id
+        let query_id = match db.get_tag_id("test tag").unwrap() {
+            TagQueryResult::TagNotFound => panic!("Tag should exist and show up in query!)"),
+            TagQueryResult::TagId(i) => i,
+        };
+
+        assert_eq!(add_id, query_id);
+    }
+
+    #[test]
+    fn remove_tag_removes_linktags() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = add(&db, entry("https://example.com", &["tag 0", "tag 1"]));
+        let tag_id = match db.get_tag_id("tag 0").unwrap() {
+            TagQueryResult::TagNotFound => panic!("Tag should have been added with the entry!"),
+            TagQueryResult::TagId(i) => i,
+        };
+
+        assert!(db.remove_tag("tag 0").unwrap());
+        assert!(!db.contains_tag("tag 0").unwrap());
+
+        let tag_links: i64 = db
+            .conn
+            .query_row(
+                "select count(*) from linktags where tag_id == ?1",
+                params![tag_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(tag_links, 0);
+
+        // The other tag on the link should be untouched
+        let link_links: i64 = db
+            .conn
+            .query_row(
+                "select count(*) from linktags where link_id == ?1",
+                params![link_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(link_links, 1);
+    }
+
+    #[test]
+    fn delete_entry_removes_linktags() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = add(&db, entry("https://example.com", &["tag 0", "tag 1"]));
+
+        assert!(db.delete_entry(link_id).unwrap());
+        assert_eq!(db.get_entry(link_id).unwrap(), None);
+        assert!(!db.delete_entry(link_id).unwrap());
+
+        let link_links: i64 = db
+            .conn
+            .query_row(
+                "select count(*) from linktags where link_id == ?1",
+                params![link_id],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(link_links, 0);
+
+        // The tags should still be there for reuse
+        assert!(db.contains_tag("tag 0").unwrap());
+        assert!(db.contains_tag("tag 1").unwrap());
+    }
+
+    #[test]
+    fn rename_tag_keeps_links() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = add(&db, entry("https://example.com", &["pyton"]));
+        let old_id = db.get_tag_id("pyton").unwrap();
+
+        assert!(db.rename_tag("pyton", "python").unwrap());
+        assert_eq!(db.get_tag_id("python").unwrap(), old_id);
+        assert_eq!(db.get_tags_for_link(link_id).unwrap(), vec!["python"]);
+    }
+
+    #[test]
+    fn rename_tag_old_missing() {
+        let db = Database::open_in_memory().unwrap();
+        assert!(!db.rename_tag("pyton", "python").unwrap());
+        assert!(!db.contains_tag("python").unwrap());
+    }
+
+    #[test]
+    fn rename_tag_new_exists() {
+        let db = Database::open_in_memory().unwrap();
+        db.add_tag("pyton").unwrap();
+        db.add_tag("python").unwrap();
+
+        assert_eq!(
+            db.rename_tag("pyton", "python"),
+            Err(TsundokuError::DuplicateTag("python".to_string()))
+        );
+        // Both tags should be left exactly as they were
+        assert!(db.contains_tag("pyton").unwrap());
+        assert_eq!(db.list_tags().unwrap(), vec!["python", "pyton"]);
+    }
+
+    #[test]
+    fn rename_tag_to_blank() {
+        let db = Database::open_in_memory().unwrap();
+        db.add_tag("ml").unwrap();
+
+        assert_eq!(db.rename_tag("ml", ""), Err(TsundokuError::EmptyTag));
+        assert_eq!(db.rename_tag("ml", " \t"), Err(TsundokuError::EmptyTag));
+        assert_eq!(db.list_tags().unwrap(), vec!["ml"]);
+    }
+
+    #[test]
+    fn add_entry_rejects_duplicates() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = add(&db, entry("https://example.com", &["tag 0"]));
+
+        assert_eq!(
+            db.add_entry(entry("https://example.com", &["tag 1"])),
+            Ok(EntryAddResult::LinkAlreadyExists)
+        );
+        // The rejected entry shouldn't have touched the tags either
+        assert!(!db.contains_tag("tag 1").unwrap());
+        assert_eq!(db.get_tags_for_link(link_id).unwrap(), vec!["tag 0"]);
+    }
+
+    #[test]
+    fn add_entry_allow_duplicate_adds_twice() {
+        let db = Database::open_in_memory().unwrap();
+        let first = add(&db, entry("https://example.com", &[]));
+        let second = db
+            .add_entry_allow_duplicate(entry("https://example.com", &[]))
+            .unwrap();
+
+        assert_ne!(first, second);
+        assert_eq!(db.get_link_id("https://example.com"), Ok(Some(first)));
+        assert_eq!(
+            db.add_entry(entry("https://example.com", &[])),
+            Ok(EntryAddResult::LinkAlreadyExists)
+        );
+    }
+
+    #[test]
+    fn search_links_and_comments() {
+        let db = Database::open_in_memory().unwrap();
+        let mut blues = entry("https://music.stackexchange.com/questions/99546", &[]);
+        blues.comment = Some("Major and minor pentatonics while playing the Blues");
+        let blues_id = add(&db, blues);
+        let mut clap = entry(
+            "https://docs.rs/clap/2.33.1/clap/struct.SubCommand.html",
+            &[],
+        );
+        clap.comment = Some("Docs for 100% of the clap library");
+        let clap_id = add(&db, clap);
+
+        let ids = |query| {
+            db.search(query)
+                .unwrap()
+                .iter()
+                .map(|e| e.id)
+                .collect::<Vec<i64>>()
+        };
+        assert_eq!(ids("blues"), vec![blues_id]);
+        assert_eq!(ids("DOCS.RS"), vec![clap_id]);
+        assert_eq!(ids("100%"), vec![clap_id]);
+        // Wildcards in the query should only match themselves
+        assert_eq!(ids("%"), vec![clap_id]);
+        assert_eq!(ids("_"), Vec::<i64>::new());
+        assert_eq!(ids("clap"), vec![clap_id]);
+        assert_eq!(ids("https"), vec![blues_id, clap_id]);
+    }
+
+    #[test]
+    fn open_from_path_persists() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tsd.db");
+
+        let link_id = {
+            let db = Database::open_from_path(&path).unwrap();
+            add(&db, entry("https://example.com", &["tag 0"]))
+        };
+
+        let db = Database::open_from_path(&path).unwrap();
+        let entry = db.get_entry(link_id).unwrap().unwrap();
+        assert_eq!(entry.link, "https://example.com");
+        assert_eq!(entry.tags, vec!["tag 0"]);
+        assert_eq!(db.list_tags().unwrap(), vec!["tag 0"]);
+    }
+
+    #[test]
+    fn random_queued_ignores_archive() {
+        let db = Database::open_in_memory().unwrap();
+        assert_eq!(db.random_queued().unwrap(), None);
+
+        let read_id = add(&db, entry("https://example.com", &[]));
+        db.mark_as_read(read_id).unwrap();
+        assert_eq!(db.random_queued().unwrap(), None);
+
+        let queued_id = add(&db, entry("https://example.org", &[]));
+        assert_eq!(db.random_queued().unwrap().map(|e| e.id), Some(queued_id));
+    }
+
+    #[test]
+    fn archive_states_round_trip() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = add(&db, entry("https://example.com", &[]));
+
+        for &archive in &[
+            Archive::Archive,
+            Archive::InProgress,
+            Archive::ReReadLater,
+            Archive::Queue,
+        ] {
+            assert!(db.set_archive(link_id, archive).unwrap());
+            assert_eq!(db.get_entry(link_id).unwrap().unwrap().archive, archive);
+            assert_eq!(Archive::try_from(archive as u8), Ok(archive));
+        }
+    }
+
+    #[test]
+    fn archive_invalid_state_fails() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = add(&db, entry("https://example.com", &[]));
+        db.conn
+            .execute(
+                "update links set archive = 42 where link_id == ?1",
+                params![link_id],
+            )
+            .unwrap();
+
+        assert_eq!(Archive::try_from(42), Err(42));
+        assert!(db.get_entry(link_id).is_err());
+    }
+
+    #[test]
+    fn entry_builder_defaults() {
+        let before = chrono::Utc::now().naive_utc();
+        let entry = EntryBuilder::new()
+            .link("https://example.com")
+            .build()
+            .unwrap();
+        let after = chrono::Utc::now().naive_utc();
+
+        assert_eq!(entry.link, "https://example.com");
+        assert_eq!(entry.comment, None);
+        assert_eq!(entry.tags, None);
+        assert_eq!(entry.archive, Archive::Queue);
+        assert!(before <= entry.timestamp && entry.timestamp <= after);
+    }
+
+    #[test]
+    fn entry_builder_missing_link() {
+        let result = EntryBuilder::new().tags(vec!["tag 0", "tag 1"]).build();
+        assert!(matches!(result, Err(TsundokuError::MissingLink)));
+    }
+
+    #[test]
+    fn update_comment_after_adding() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = add(&db, entry("https://example.com", &[]));
+        assert_eq!(db.get_entry(link_id).unwrap().unwrap().comment, None);
+
+        assert!(db.update_comment(link_id, Some("An example")).unwrap());
+        assert_eq!(
+            db.get_entry(link_id).unwrap().unwrap().comment,
+            Some("An example".to_string())
+        );
+
+        assert!(db.update_comment(link_id, None).unwrap());
+        assert_eq!(db.get_entry(link_id).unwrap().unwrap().comment, None);
+
+        assert!(!db
+            .update_comment(link_id + 1, Some("Nothing here"))
+            .unwrap());
+    }
+
+    #[test]
+    fn list_tags_with_counts_ordering() {
+        let db = Database::open_in_memory().unwrap();
+        db.add_tag("unused").unwrap();
+        add(&db, entry("https://example.com/1", &["rust", "cli"]));
+        add(&db, entry("https://example.com/2", &["rust", "blues"]));
+        add(&db, entry("https://example.com/3", &["rust", "cli"]));
+        add(&db, entry("https://example.com/4", &["async", "Zig"]));
+        add(&db, entry("https://example.com/5", &["Blues"]));
+
+        let counts = db.list_tags_with_counts().unwrap();
+        let expected: Vec<(String, i64)> = vec![
+            ("rust".to_string(), 3),
+            ("blues".to_string(), 2),
+            ("cli".to_string(), 2),
+            ("async".to_string(), 1),
+            ("Zig".to_string(), 1),
+            ("unused".to_string(), 0),
+        ];
+        assert_eq!(counts, expected);
+    }
+
+    #[test]
+    fn list_entries_in_pages() {
+        let db = Database::open_in_memory().unwrap();
+        let links: Vec<String> = (0..10)
+            .map(|i| format!("https://example.com/{}", i))
+            .collect();
+        let ids: Vec<i64> = links.iter().map(|l| add(&db, entry(l, &[]))).collect();
+        assert_eq!(db.count_entries().unwrap(), 10);
+
+        let page = |limit, offset| -> Vec<i64> {
+            db.list_entries_paged(limit, offset)
+                .unwrap()
+                .iter()
+                .map(|e| e.id)
+                .collect()
+        };
+        assert_eq!(page(3, 0), ids[0..3].to_vec());
+        assert_eq!(page(3, 3), ids[3..6].to_vec());
+        assert_eq!(page(3, 6), ids[6..9].to_vec());
+        assert_eq!(page(3, 9), ids[9..].to_vec());
+        assert_eq!(page(3, 12), Vec::<i64>::new());
+        assert_eq!(page(0, 0), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn list_entries_sorted_by_timestamp() {
+        let db = Database::open_in_memory().unwrap();
+        let at = |day, hour, nano| {
+            chrono::NaiveDate::from_ymd_opt(2020, 6, day)
+                .unwrap()
+                .and_hms_nano_opt(hour, 0, 0, nano)
+                .unwrap()
+        };
+        // Add them out of order, including two a fraction of a second apart.
+        let mut middle = entry("https://example.com/middle", &[]);
+        middle.timestamp = at(2, 9, 500_000_000);
+        let middle = add(&db, middle);
+        let mut newest = entry("https://example.com/newest", &[]);
+        newest.timestamp = at(10, 9, 0);
+        let newest = add(&db, newest);
+        let mut oldest = entry("https://example.com/oldest", &[]);
+        oldest.timestamp = at(2, 9, 0);
+        let oldest = add(&db, oldest);
+
+        let ids = |order| -> Vec<i64> {
+            db.list_entries_sorted(order)
+                .unwrap()
+                .iter()
+                .map(|e| e.id)
+                .collect()
+        };
+        assert_eq!(ids(SortOrder::OldestFirst), vec![oldest, middle, newest]);
+        assert_eq!(ids(SortOrder::NewestFirst), vec![newest, middle, oldest]);
+    }
+
+    #[test]
+    fn list_entries_by_archive_state() {
+        let db = Database::open_in_memory().unwrap();
+        let first = add(&db, entry("https://example.com/1", &[]));
+        let second = add(&db, entry("https://example.com/2", &[]));
+        let third = add(&db, entry("https://example.com/3", &[]));
+        db.mark_as_read(second).unwrap();
+
+        let ids = |state| -> Vec<i64> {
+            db.list_entries_by_archive(state)
+                .unwrap()
+                .iter()
+                .map(|e| e.id)
+                .collect()
+        };
+        assert_eq!(ids(Archive::Queue), vec![first, third]);
+        assert_eq!(ids(Archive::Archive), vec![second]);
+        assert_eq!(ids(Archive::InProgress), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn add_entry_validated_valid_url() {
+        let db = Database::open_in_memory().unwrap();
+        let result = db.add_entry_validated(entry("https://example.com/a?b=c", &[]), false);
+        assert!(matches!(result, Ok(EntryAddResult::LinkId(_))));
+        assert!(db.contains_link("https://example.com/a?b=c").unwrap());
+    }
+
+    #[test]
+    fn add_entry_validated_normalizes_bare_domains() {
+        let db = Database::open_in_memory().unwrap();
+        assert_eq!(
+            db.add_entry_validated(entry("example.com/path", &[]), false),
+            Err(TsundokuError::InvalidUrl("example.com/path".to_string()))
+        );
+
+        let link_id = match db.add_entry_validated(entry("example.com/path", &[]), true) {
+            Ok(EntryAddResult::LinkId(i)) => i,
+            other => panic!("expected the link to be added, got {:?}", other),
+        };
+        assert_eq!(
+            db.get_entry(link_id).unwrap().unwrap().link,
+            "https://example.com/path"
+        );
+    }
+
+    #[test]
+    fn add_entry_validated_normalizes_host_and_port() {
+        let db = Database::open_in_memory().unwrap();
+        for &link in &["localhost:3000", "example.com:8080/x"] {
+            assert_eq!(
+                db.add_entry_validated(entry(link, &[]), false),
+                Err(TsundokuError::InvalidUrl(link.to_string()))
+            );
+            let link_id = match db.add_entry_validated(entry(link, &[]), true) {
+                Ok(EntryAddResult::LinkId(i)) => i,
+                other => panic!("expected {} to be added, got {:?}", link, other),
+            };
+            assert_eq!(
+                db.get_entry(link_id).unwrap().unwrap().link,
+                format!("https://{}", link)
+            );
+        }
+    }
+
+    #[test]
+    fn add_entry_validated_rejects_garbage() {
+        let db = Database::open_in_memory().unwrap();
+        let links = [
+            "",
+            "   ",
+            "not a url",
+            "https://",
+            "://nothing",
+            "ftp://example.com",
+            "file:///etc/passwd",
+        ];
+        for &link in &links {
+            for &normalize in &[false, true] {
+                assert_eq!(
+                    db.add_entry_validated(entry(link, &[]), normalize),
+                    Err(TsundokuError::InvalidUrl(link.to_string()))
+                );
+            }
+        }
+        assert_eq!(db.count_entries().unwrap(), 0);
+    }
+
+    #[test]
+    fn add_entry_defaults_timestamp_to_now() {
+        let db = Database::open_in_memory().unwrap();
+        let before = chrono::Utc::now().naive_utc();
+        let built = EntryBuilder::new()
+            .link("https://example.com")
+            .build()
+            .unwrap();
+        let link_id = add(&db, built);
+        let after = chrono::Utc::now().naive_utc();
+
+        let timestamp = db.get_entry(link_id).unwrap().unwrap().timestamp;
+        assert!(before <= timestamp && timestamp <= after);
+    }
+
+    #[test]
+    fn get_links_for_tags_all_and_any() {
+        let db = Database::open_in_memory().unwrap();
+        let tokio = add(&db, entry("https://tokio.rs", &["rust", "async"]));
+        let book = add(&db, entry("https://doc.rust-lang.org/book", &["rust"]));
+        let goroutines = add(&db, entry("https://go.dev/tour", &["go", "async"]));
+        add(&db, entry("https://example.com", &["other"]));
+
+        let ids = |tags: &[&str], mode| -> Vec<i64> {
+            db.get_links_for_tags(tags, mode)
+                .unwrap()
+                .iter()
+                .map(|e| e.id)
+                .collect()
+        };
+        assert_eq!(ids(&["rust", "async"], TagMatch::All), vec![tokio]);
+        assert_eq!(
+            ids(&["rust", "async"], TagMatch::Any),
+            vec![tokio, book, goroutines]
+        );
+        assert_eq!(ids(&["rust", "go"], TagMatch::All), Vec::<i64>::new());
+        assert_eq!(
+            ids(&["rust", "go"], TagMatch::Any),
+            vec![tokio, book, goroutines]
+        );
+        // Repeating a tag shouldn't change what "all" means
+        assert_eq!(ids(&["rust", "rust"], TagMatch::All), vec![tokio, book]);
+        // Tags that don't exist can never be matched
+        assert_eq!(ids(&["rust", "missing"], TagMatch::All), Vec::<i64>::new());
+        assert_eq!(ids(&["rust", "missing"], TagMatch::Any), vec![tokio, book]);
+        assert_eq!(ids(&[], TagMatch::All), Vec::<i64>::new());
+        assert_eq!(ids(&[], TagMatch::Any), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn untag_link_keeps_other_tags() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = add(&db, entry("https://example.com", &["tag 0", "tag 1"]));
+        let other_id = add(&db, entry("https://example.org", &["tag 0"]));
+
+        assert!(db.untag_link(link_id, "tag 0").unwrap());
+        assert_eq!(db.get_tags_for_link(link_id).unwrap(), vec!["tag 1"]);
+        // Other entries, and the tag itself, should be left alone
+        assert_eq!(db.get_tags_for_link(other_id).unwrap(), vec!["tag 0"]);
+        assert!(db.contains_tag("tag 0").unwrap());
+
+        assert!(!db.untag_link(link_id, "tag 0").unwrap());
+        assert!(!db.untag_link(link_id, "missing").unwrap());
+    }
+
+    #[test]
+    fn open_from_path_migrates_old_schema() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tsd.db");
+        {
+            // A database as created before the archive and timestamp columns
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "
+                create table links (
+                    link_id              INTEGER PRIMARY KEY AUTOINCREMENT,
+                    link            TEXT NOT NULL,
+                    comment         TEXT
+                );
+                create table tags (
+                    tag_id              INTEGER PRIMARY KEY AUTOINCREMENT,
+                    tag             TEXT NOT NULL
+                );
+                create table linktags (
+                    link_id         INTEGER,
+                    tag_id          INTEGER,
+                    FOREIGN KEY(link_id) REFERENCES links(link_id)
+                    FOREIGN KEY(tag_id) REFERENCES tags(tag_id)
+                );
+                insert into links (link, comment) values ('https://example.com', 'An example');
+                insert into tags (tag) values ('tag 0');
+                insert into linktags (link_id, tag_id) values (1, 1);",
+            )
+            .unwrap();
+        }
+
+        let before = chrono::Utc::now().naive_utc();
+        let db = Database::open_from_path(&path).unwrap();
+        let version: i64 = db
+            .conn
+            .pragma_query_value(None, "user_version", |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len() as i64);
+
+        let old = db.get_entry(1).unwrap().unwrap();
+        assert_eq!(old.link, "https://example.com");
+        assert_eq!(old.comment, Some("An example".to_string()));
+        assert_eq!(old.tags, vec!["tag 0"]);
+        assert_eq!(old.archive, Archive::Queue);
+        assert!(before <= old.timestamp);
+        assert_eq!(old.source, None);
+
+        // The migrated database should work like any other
+        let new_id = add(&db, entry("https://example.org", &["tag 0"]));
+        assert!(db.mark_as_read(new_id).unwrap());
+        assert_eq!(
+            db.list_entries_by_archive(Archive::Queue).unwrap(),
+            vec![old]
+        );
+
+        // And opening it again shouldn't run the migrations a second time
+        drop(db);
+        let db = Database::open_from_path(&path).unwrap();
+        assert_eq!(db.count_entries().unwrap(), 2);
+    }
+
+    #[test]
+    fn duplicate_tags_rejected_by_index() {
+        let db = Database::open_in_memory().unwrap();
+        db.add_tag("tag 0").unwrap();
+
+        for tag in &["tag 0", "TAG 0"] {
+            let result = db
+                .conn
+                .execute("insert into tags (tag) values (?1)", params![tag]);
+            assert!(result.is_err());
+        }
+        assert_eq!(db.list_tags().unwrap(), vec!["tag 0"]);
+    }
+
+    #[test]
+    fn get_tag_by_id_reverses_add_tag() {
+        let db = Database::open_in_memory().unwrap();
+        db.add_tag("tag 0").unwrap();
+        let tag_id = match db.add_tag("tag 1").unwrap() {
+            TagAddResult::TagAlreadyExists => panic!("tag should not already exist!"),
+            TagAddResult::TagId(i) => i,
+        };
+
+        assert_eq!(db.get_tag_by_id(tag_id), Ok(Some("tag 1".to_string())));
+        assert_eq!(db.get_tag_by_id(tag_id + 1), Ok(None));
+    }
+
+    #[test]
+    fn add_entry_failure_leaves_nothing_behind() {
+        let db = Database::open_in_memory().unwrap();
+        // Make adding one particular tag fail, after the link and the tags
+        // before it have already been inserted.
+        db.conn
+            .execute(
+                "
+                create trigger reject_tag before insert on tags
+                    when new.tag == 'bad tag'
+                    begin select raise(abort, 'rejected'); end",
+                params![],
+            )
+            .unwrap();
+
+        let result = db.add_entry(entry("https://example.com", &["tag 0", "bad tag"]));
+        assert!(matches!(result, Err(TsundokuError::Sqlite(_))));
+        assert_eq!(db.count_entries().unwrap(), 0);
+        assert!(!db.contains_tag("tag 0").unwrap());
+        let tag_links: i64 = db
+            .conn
+            .query_row("select count(*) from linktags", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tag_links, 0);
+
+        // And the database should still be usable afterwards
+        add(&db, entry("https://example.com", &["tag 0"]));
+        assert_eq!(db.count_entries().unwrap(), 1);
+    }
+
+    #[test]
+    fn tags_ignore_case() {
+        let db = Database::open_in_memory().unwrap();
+        let tag_id = db.add_tag("rust").unwrap();
+
+        assert!(db.contains_tag("RUST").unwrap());
+        assert_eq!(db.get_tag_id("Rust").unwrap(), tag_id);
+        assert_eq!(db.add_tag("Rust"), Ok(TagAddResult::TagAlreadyExists));
+        assert_eq!(db.list_tags().unwrap(), vec!["rust"]);
+    }
+
+    #[test]
+    fn entries_share_tags_ignoring_case() {
+        let db = Database::open_in_memory().unwrap();
+        let lower = add(&db, entry("https://www.rust-lang.org", &["rust"]));
+        let upper = add(&db, entry("https://doc.rust-lang.org", &["Rust", "DOCS"]));
+
+        assert_eq!(db.list_tags().unwrap(), vec!["DOCS", "rust"]);
+        assert_eq!(db.get_tags_for_link(upper).unwrap(), vec!["rust", "DOCS"]);
+        let ids = |entries: Vec<OwnedEntry>| -> Vec<i64> { entries.iter().map(|e| e.id).collect() };
+        assert_eq!(
+            ids(db.get_links_for_tag("RUST").unwrap()),
+            vec![lower, upper]
+        );
+        assert_eq!(
+            ids(db
+                .get_links_for_tags(&["rust", "RUST", "docs"], TagMatch::All)
+                .unwrap()),
+            vec![upper]
+        );
+
+        // Changing the case of a tag is a rename, not a clash
+        assert!(db.rename_tag("DOCS", "docs").unwrap());
+        assert_eq!(db.get_tags_for_link(upper).unwrap(), vec!["rust", "docs"]);
+        assert_eq!(
+            db.rename_tag("docs", "Rust"),
+            Err(TsundokuError::DuplicateTag("Rust".to_string()))
+        );
+    }
+
+    #[test]
+    fn stats_summarise_the_pile() {
+        let db = Database::open_in_memory().unwrap();
+        let at = |day| {
+            chrono::NaiveDate::from_ymd_opt(2020, 6, day)
+                .unwrap()
+                .and_hms_opt(9, 0, 0)
+                .unwrap()
+        };
+        let mut ids = vec![];
+        for (i, &day) in [2, 10, 5, 7].iter().enumerate() {
+            let link = format!("https://example.com/{}", i);
+            let mut entry = entry(&link, &["tag 0", if i % 2 == 0 { "even" } else { "odd" }]);
+            entry.timestamp = at(day);
+            ids.push(add(&db, entry));
+        }
+        db.add_tag("unused").unwrap();
+        db.mark_as_read(ids[0]).unwrap();
+        db.set_archive(ids[1], Archive::InProgress).unwrap();
+
+        assert_eq!(
+            db.stats().unwrap(),
+            Stats {
+                total: 4,
+                queued: 2,
+                archived: 1,
+                tags: 4,
+                latest: Some(at(10)),
+            }
+        );
+    }
+
+    #[test]
+    fn random_queued_for_tag_stays_on_topic() {
+        let db = Database::open_in_memory().unwrap();
+        for i in 0..10 {
+            let link = format!("https://example.com/{}", i);
+            add(&db, entry(&link, &[if i % 2 == 0 { "rust" } else { "go" }]));
+        }
+        let read_id = add(&db, entry("https://example.com/read", &["blues"]));
+        db.mark_as_read(read_id).unwrap();
+
+        for _ in 0..20 {
+            let entry = db.random_queued_for_tag("rust").unwrap().unwrap();
+            assert_eq!(entry.tags, vec!["rust"]);
+            assert_eq!(entry.archive, Archive::Queue);
+        }
+        assert_eq!(db.random_queued_for_tag("blues").unwrap(), None);
+        assert_eq!(db.random_queued_for_tag("python").unwrap(), None);
+    }
+
+    #[test]
+    fn requeue_after_reading() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = add(&db, entry("https://example.com", &[]));
+        db.mark_as_read(link_id).unwrap();
+        assert_eq!(db.list_entries_by_archive(Archive::Queue).unwrap(), vec![]);
+
+        assert!(db.requeue(link_id).unwrap());
+        let queued = db.list_entries_by_archive(Archive::Queue).unwrap();
+        assert_eq!(queued.len(), 1);
+        assert_eq!(queued[0].id, link_id);
+        assert!(!db.requeue(link_id + 1).unwrap());
+    }
+
+    #[test]
+    fn merge_tags_without_duplicates() {
+        let db = Database::open_in_memory().unwrap();
+        let both = add(
+            &db,
+            entry("https://example.com/both", &["ml", "machine-learning"]),
+        );
+        let source = add(&db, entry("https://example.com/source", &["ml", "python"]));
+        let dest = add(
+            &db,
+            entry("https://example.com/dest", &["machine-learning"]),
+        );
+        let neither = add(&db, entry("https://example.com/neither", &["python"]));
+
+        assert!(db.merge_tags("ml", "machine-learning").unwrap());
+        assert!(!db.contains_tag("ml").unwrap());
+        assert_eq!(
+            db.get_tags_for_link(both).unwrap(),
+            vec!["machine-learning"]
+        );
+        assert_eq!(
+            db.get_tags_for_link(source).unwrap(),
+            vec!["machine-learning", "python"]
+        );
+        assert_eq!(
+            db.get_tags_for_link(dest).unwrap(),
+            vec!["machine-learning"]
+        );
+        assert_eq!(db.get_tags_for_link(neither).unwrap(), vec!["python"]);
+
+        let duplicates: i64 = db
+            .conn
+            .query_row(
+                "
+                select count(*) from (
+                    select link_id, tag_id from linktags
+                        group by link_id, tag_id
+                        having count(*) > 1
+                )",
+                params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(duplicates, 0);
+
+        assert!(!db.merge_tags("ml", "machine-learning").unwrap());
+        assert!(!db.merge_tags("python", "missing").unwrap());
+        assert!(db.contains_tag("python").unwrap());
+    }
+
+    #[test]
+    fn tags_with_prefix_sorted_subset() {
+        let db = Database::open_in_memory().unwrap();
+        for tag in &[
+            "rust",
+            "python",
+            "Rustacean",
+            "rust_async",
+            "rustup",
+            "ruby",
+            "go",
+        ] {
+            db.add_tag(tag).unwrap();
+        }
+
+        assert_eq!(
+            db.tags_with_prefix("rust").unwrap(),
+            vec!["rust", "rust_async", "Rustacean", "rustup"]
+        );
+        assert_eq!(db.tags_with_prefix("RU").unwrap().len(), 5);
+        // Wildcards in the prefix should only match themselves
+        assert_eq!(db.tags_with_prefix("rust_").unwrap(), vec!["rust_async"]);
+        assert_eq!(db.tags_with_prefix("%").unwrap(), Vec::<String>::new());
+        assert_eq!(db.tags_with_prefix("java").unwrap(), Vec::<String>::new());
+        assert_eq!(db.tags_with_prefix("").unwrap().len(), 7);
+    }
+
+    #[test]
+    fn list_recent_newest_first() {
+        let db = Database::open_in_memory().unwrap();
+        let mut ids = vec![];
+        for day in &[3, 1, 5, 2, 4] {
+            let link = format!("https://example.com/{}", day);
+            let mut entry = entry(&link, &[]);
+            entry.timestamp = chrono::NaiveDate::from_ymd_opt(2020, 6, *day)
+                .unwrap()
+                .and_hms_opt(9, 0, 0)
+                .unwrap();
+            ids.push(add(&db, entry));
+        }
+        db.mark_as_read(ids[2]).unwrap();
+
+        let recent = |n| -> Vec<i64> { db.list_recent(n).unwrap().iter().map(|e| e.id).collect() };
+        // Days 5, 4 and 3, even though day 5 has been read
+        assert_eq!(recent(3), vec![ids[2], ids[4], ids[0]]);
+        assert_eq!(recent(10), vec![ids[2], ids[4], ids[0], ids[3], ids[1]]);
+        assert_eq!(recent(0), Vec::<i64>::new());
+    }
+
+    #[test]
+    fn tag_links_in_bulk() {
+        let db = Database::open_in_memory().unwrap();
+        let first = add(&db, entry("https://example.com/1", &[]));
+        let second = add(&db, entry("https://example.com/2", &["imported"]));
+        let third = add(&db, entry("https://example.com/3", &["batch"]));
+        let untouched = add(&db, entry("https://example.com/4", &[]));
+
+        // The third link already has the tag, and the last id doesn't exist
+        assert_eq!(
+            db.tag_links(&[first, second, third, first, untouched + 1], "batch"),
+            Ok(2)
+        );
+        assert_eq!(db.get_tags_for_link(first).unwrap(), vec!["batch"]);
+        assert_eq!(
+            db.get_tags_for_link(second).unwrap(),
+            vec!["imported", "batch"]
+        );
+        assert_eq!(db.get_tags_for_link(third).unwrap(), vec!["batch"]);
+        assert!(db.get_tags_for_link(untouched).unwrap().is_empty());
+
+        let batch: i64 = db
+            .conn
+            .query_row(
+                "
+                select count(*) from linktags
+                    inner join tags on tags.tag_id == linktags.tag_id
+                    where tags.tag == 'batch'",
+                params![],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(batch, 3);
+
+        assert_eq!(db.tag_links(&[untouched], "new tag"), Ok(1));
+        assert_eq!(db.get_tags_for_link(untouched).unwrap(), vec!["new tag"]);
+    }
+
+    #[test]
+    fn compact_populated_database() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = Database::open_from_path(dir.path().join("tsd.db")).unwrap();
+        let links: Vec<String> = (0..100)
+            .map(|i| format!("https://example.com/{}", i))
+            .collect();
+        let ids: Vec<i64> = links
+            .iter()
+            .map(|l| add(&db, entry(l, &["tag 0"])))
+            .collect();
+        for id in &ids[..50] {
+            db.delete_entry(*id).unwrap();
+        }
+
+        db.compact().unwrap();
+        assert_eq!(db.count_entries(), Ok(50));
+        assert_eq!(db.get_links_for_tag("tag 0").unwrap().len(), 50);
+    }
+
+    #[test]
+    fn backup_in_memory_to_file() {
+        let db = Database::open_in_memory().unwrap();
+        let mut commented = entry("https://example.com", &["tag 0", "tag 1"]);
+        commented.comment = Some("An example");
+        add(&db, commented);
+        let read_id = add(&db, entry("https://example.org", &["tag 1"]));
+        db.mark_as_read(read_id).unwrap();
+        db.add_tag("unused").unwrap();
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("backup.db");
+        db.backup(&path).unwrap();
+
+        let restored = Database::open_from_path(&path).unwrap();
+        assert_eq!(restored.list_entries(), db.list_entries());
+        assert_eq!(restored.list_tags(), db.list_tags());
+        assert_eq!(restored.count_entries(), Ok(2));
+    }
+
+    #[test]
+    fn dedupe_links_keeps_union_of_tags() {
+        let db = Database::open_in_memory().unwrap();
+        let mut first = entry("https://example.com", &["tag 0"]);
+        first.comment = Some("The original");
+        let first = db.add_entry_allow_duplicate(first).unwrap();
+        let other = add(&db, entry("https://example.org", &["tag 2"]));
+        let second = db
+            .add_entry_allow_duplicate(entry("https://example.com", &["tag 1", "tag 0"]))
+            .unwrap();
+        let third = db
+            .add_entry_allow_duplicate(entry("https://example.com", &["tag 2"]))
+            .unwrap();
+
+        assert_eq!(
+            db.find_duplicate_links(),
+            Ok(vec![(
+                "https://example.com".to_string(),
+                vec![first, second, third]
+            )])
+        );
+
+        assert_eq!(db.dedupe_links(), Ok(2));
+        assert_eq!(db.find_duplicate_links(), Ok(vec![]));
+        let ids: Vec<i64> = db.list_entries().unwrap().iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![first, other]);
+
+        let survivor = db.get_entry(first).unwrap().unwrap();
+        assert_eq!(survivor.tags, vec!["tag 0", "tag 2", "tag 1"]);
+        assert_eq!(survivor.comment, Some("The original".to_string()));
+        assert_eq!(db.get_tags_for_link(other).unwrap(), vec!["tag 2"]);
+
+        let orphans: i64 = db
+            .conn
+            .query_row(
+                "select count(*) from linktags where link_id in (?1, ?2)",
+                params![second, third],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(orphans, 0);
+        assert_eq!(db.dedupe_links(), Ok(0));
+    }
+
+    #[test]
+    fn count_links_for_tag_counts() {
+        let db = Database::open_in_memory().unwrap();
+        for i in 0..5 {
+            let link = format!("https://example.com/{}", i);
+            add(&db, entry(&link, if i < 3 { &["rust"] } else { &["go"] }));
+        }
+        db.add_tag("unused").unwrap();
+
+        assert_eq!(db.count_links_for_tag("rust"), Ok(3));
+        assert_eq!(db.count_links_for_tag("go"), Ok(2));
+        assert_eq!(db.count_links_for_tag("unused"), Ok(0));
+        assert_eq!(db.count_links_for_tag("missing"), Ok(0));
+    }
+
+    #[test]
+    fn prune_unused_tags_keeps_linked() {
+        let db = Database::open_in_memory().unwrap();
+        db.add_tag("never used").unwrap();
+        add(&db, entry("https://example.com", &["tag 0", "tag 1"]));
+        let deleted = add(&db, entry("https://example.org", &["tag 1", "tag 2"]));
+        let untagged = add(&db, entry("https://example.net", &["tag 3"]));
+        db.delete_entry(deleted).unwrap();
+        db.untag_link(untagged, "tag 3").unwrap();
+
+        assert_eq!(db.prune_unused_tags(), Ok(3));
+        assert_eq!(db.list_tags().unwrap(), vec!["tag 0", "tag 1"]);
+        assert_eq!(db.prune_unused_tags(), Ok(0));
+    }
+
+    #[test]
+    fn archive_all_only_archives_queued() {
+        let db = Database::open_in_memory().unwrap();
+        let queued = add(&db, entry("https://example.com/queued", &[]));
+        let also_queued = add(&db, entry("https://example.com/also-queued", &[]));
+        let in_progress = add(&db, entry("https://example.com/in-progress", &[]));
+        let later = add(&db, entry("https://example.com/later", &[]));
+        let read = add(&db, entry("https://example.com/read", &[]));
+        db.set_archive(in_progress, Archive::InProgress).unwrap();
+        db.set_archive(later, Archive::ReReadLater).unwrap();
+        db.mark_as_read(read).unwrap();
+
+        assert_eq!(db.archive_all(), Ok(2));
+        let archive = |link_id| db.get_entry(link_id).unwrap().unwrap().archive;
+        assert_eq!(archive(queued), Archive::Archive);
+        assert_eq!(archive(also_queued), Archive::Archive);
+        assert_eq!(archive(in_progress), Archive::InProgress);
+        assert_eq!(archive(later), Archive::ReReadLater);
+        assert_eq!(archive(read), Archive::Archive);
+        assert_eq!(db.archive_all(), Ok(0));
+    }
+
+    #[test]
+    fn for_each_entry_visits_everything() {
+        let db = Database::open_in_memory().unwrap();
+        let links: Vec<String> = (0..25)
+            .map(|i| format!("https://example.com/{}", i))
+            .collect();
+        for link in &links {
+            add(&db, entry(link, &["tag 0"]));
+        }
+        db.mark_as_read(3).unwrap();
+
+        let mut visited = 0;
+        db.for_each_entry(|entry| {
+            visited += 1;
+            assert_eq!(entry.id, visited);
+            assert_eq!(entry.tags, vec!["tag 0"]);
+            Ok(())
+        })
+        .unwrap();
+        assert_eq!(Ok(visited), db.count_entries());
+
+        // Errors from the callback should stop the visit, and be passed on
+        let mut visited = 0;
+        let result = db.for_each_entry(|_| {
+            visited += 1;
+            Err(TsundokuError::NotFound)
+        });
+        assert_eq!(result, Err(TsundokuError::NotFound));
+        assert_eq!(visited, 1);
+    }
+
+    #[test]
+    fn add_entry_keeps_archive_state() {
+        let db = Database::open_in_memory().unwrap();
+        let mut read = entry("https://example.com", &[]);
+        read.archive = Archive::Archive;
+        let read_id = add(&db, read);
+        let queued_id = add(&db, entry("https://example.org", &[]));
+
+        assert_eq!(
+            db.get_entry(read_id).unwrap().unwrap().archive,
+            Archive::Archive
+        );
+        assert_eq!(
+            db.get_entry(queued_id).unwrap().unwrap().archive,
+            Archive::Queue
+        );
+    }
+
+    #[test]
+    fn list_tags_sorted() {
+        let db = Database::open_in_memory().unwrap();
+        for tag in &["rust", "Blues", "async", "cli", "Zig"] {
+            db.add_tag(tag).unwrap();
+        }
+        assert_eq!(
+            db.list_tags().unwrap(),
+            vec!["async", "Blues", "cli", "rust", "Zig"]
+        );
+
+        // Removing a tag and adding it back shouldn't move it
+        db.remove_tag("async").unwrap();
+        db.add_tag("async").unwrap();
+        assert_eq!(db.list_tags().unwrap()[0], "async");
+    }
+
+    #[test]
+    fn from_connection_keeps_pragmas() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.pragma_update(None, "foreign_keys", &true).unwrap();
+        let db = Database::from_connection(conn).unwrap();
+
+        let link_id = add(&db, entry("https://example.com", &["tag 0"]));
+        assert_eq!(db.get_tags_for_link(link_id).unwrap(), vec!["tag 0"]);
+        let foreign_keys: bool = db
+            .conn
+            .pragma_query_value(None, "foreign_keys", |row| row.get(0))
+            .unwrap();
+        assert!(foreign_keys);
+
+        // With foreign keys on, linking to a missing entry now fails
+        let tag_id = match db.get_tag_id("tag 0").unwrap() {
+            TagQueryResult::TagNotFound => panic!("Tag should have been added with the entry!"),
+            TagQueryResult::TagId(i) => i,
+        };
+        assert!(db
+            .conn
+            .execute(
+                "insert into linktags (link_id, tag_id) values (?1, ?2)",
+                params![link_id + 1, tag_id],
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn upsert_entry_merges_tags() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = db
+            .upsert_entry(entry("https://example.com", &["a"]))
+            .unwrap();
+        db.mark_as_read(link_id).unwrap();
+
+        let mut again = entry("https://example.com", &["b", "a"]);
+        again.comment = Some("Added later");
+        assert_eq!(db.upsert_entry(again), Ok(link_id));
+        assert_eq!(db.count_entries(), Ok(1));
+
+        let merged = db.get_entry(link_id).unwrap().unwrap();
+        assert_eq!(merged.tags, vec!["a", "b"]);
+        assert_eq!(merged.comment, Some("Added later".to_string()));
+        assert_eq!(merged.archive, Archive::Archive);
+
+        // An existing comment shouldn't be replaced
+        let mut replacement = entry("https://example.com", &[]);
+        replacement.comment = Some("Replacement");
+        assert_eq!(db.upsert_entry(replacement), Ok(link_id));
+        assert_eq!(
+            db.get_entry(link_id).unwrap().unwrap().comment,
+            Some("Added later".to_string())
+        );
+    }
+
+    #[test]
+    fn list_untagged_only() {
+        let db = Database::open_in_memory().unwrap();
+        let untagged = add(&db, entry("https://example.com/1", &[]));
+        add(&db, entry("https://example.com/2", &["tag 0"]));
+        let read = add(&db, entry("https://example.com/3", &[]));
+        db.mark_as_read(read).unwrap();
+        let untagged_later = add(&db, entry("https://example.com/4", &["tag 1"]));
+        db.untag_link(untagged_later, "tag 1").unwrap();
+
+        let ids: Vec<i64> = db.list_untagged().unwrap().iter().map(|e| e.id).collect();
+        assert_eq!(ids, vec![untagged, read, untagged_later]);
+    }
+
+    #[test]
+    fn get_or_create_tag_once() {
+        let db = Database::open_in_memory().unwrap();
+        let tag_id = db.get_or_create_tag("tag 0").unwrap();
+        assert_eq!(db.get_or_create_tag("tag 0"), Ok(tag_id));
+        assert_ne!(db.get_or_create_tag("tag 1"), Ok(tag_id));
+
+        let tags: i64 = db
+            .conn
+            .query_row("select count(*) from tags", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(tags, 2);
+    }
+
+    #[test]
+    fn random_queued_excluding_skips_shown() {
+        let db = Database::open_in_memory().unwrap();
+        let shown = add(&db, entry("https://example.com/1", &[]));
+        let read = add(&db, entry("https://example.com/2", &[]));
+        db.mark_as_read(read).unwrap();
+        let remaining = add(&db, entry("https://example.com/3", &[]));
+
+        // Pick enough times that we'd expect to hit the shown entry if it
+        // wasn't excluded
+        for _ in 0..20 {
+            let picked = db.random_queued_excluding(&[shown]).unwrap().unwrap();
+            assert_eq!(picked.id, remaining);
+        }
+        assert_eq!(db.random_queued_excluding(&[shown, remaining]), Ok(None));
+    }
+
+    #[test]
+    fn counts_by_archive_grouped() {
+        let db = Database::open_in_memory().unwrap();
+        for i in 0..6 {
+            add(&db, entry(&format!("https://example.com/{}", i), &[]));
+        }
+        db.set_archive(1, Archive::Archive).unwrap();
+        db.set_archive(2, Archive::Archive).unwrap();
+        db.set_archive(3, Archive::ReReadLater).unwrap();
+
+        let counts = db.counts_by_archive().unwrap();
+        assert_eq!(counts.len(), 3);
+        assert_eq!(counts[&Archive::Queue], 3);
+        assert_eq!(counts[&Archive::Archive], 2);
+        assert_eq!(counts[&Archive::ReReadLater], 1);
+        assert!(!counts.contains_key(&Archive::InProgress));
+
+        db.conn
+            .execute("update links set archive = 9 where link_id == 4", params![])
+            .unwrap();
+        assert!(db.counts_by_archive().is_err());
+    }
+
+    #[test]
+    fn timestamps_round_trip_exactly() {
+        let db = Database::open_in_memory().unwrap();
+        let date = chrono::NaiveDate::from_ymd_opt(2020, 6, 2).unwrap();
+        let timestamp = date.and_hms_nano_opt(9, 5, 7, 123_456_789).unwrap();
+        let mut timestamped = entry("https://example.com", &[]);
+        timestamped.timestamp = timestamp;
+        let link_id = add(&db, timestamped);
+
+        let stored: String = db
+            .conn
+            .query_row("select timestamp from links", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(stored, "2020-06-02T09:05:07.123456789");
+        let read = db.get_entry(link_id).unwrap().unwrap();
+        assert_eq!(read.timestamp, timestamp);
+        assert_eq!(db.list_entries().unwrap()[0].timestamp, timestamp);
+
+        // Shorter fractions (or none at all) should still be read
+        assert_eq!(
+            parse_timestamp("2020-06-02T09:05:07"),
+            Ok(date.and_hms_opt(9, 5, 7).unwrap())
+        );
+
+        // As should sqlite's own timestamps, and ones with an offset
+        db.conn
+            .execute(
+                "update links set timestamp = '2020-06-02 09:05:07'",
+                params![],
+            )
+            .unwrap();
+        assert_eq!(
+            db.get_entry(link_id).unwrap().unwrap().timestamp,
+            date.and_hms_opt(9, 5, 7).unwrap()
+        );
+        db.conn
+            .execute(
+                "insert into links (link, timestamp) values ('https://example.org', datetime('now'))",
+                params![],
+            )
+            .unwrap();
+        assert_eq!(db.list_entries().unwrap().len(), 2);
+        assert_eq!(
+            parse_timestamp("2020-06-02T10:05:07.5+01:00"),
+            Ok(date.and_hms_milli_opt(9, 5, 7, 500).unwrap())
+        );
+
+        db.conn
+            .execute("update links set timestamp = 'last tuesday'", params![])
+            .unwrap();
+        assert_eq!(
+            db.get_entry(link_id),
+            Err(TsundokuError::BadTimestamp("last tuesday".to_string()))
+        );
+        assert!(db.stats().is_err());
+    }
+
+    #[test]
+    fn next_queued_oldest_first() {
+        let db = Database::open_in_memory().unwrap();
+        assert_eq!(db.next_queued(), Ok(None));
+
+        let at = |day| {
+            chrono::NaiveDate::from_ymd_opt(2020, 6, day)
+                .unwrap()
+                .and_hms_opt(9, 0, 0)
+                .unwrap()
+        };
+        let mut ids = vec![];
+        for (i, day) in [5, 2, 2, 1].iter().enumerate() {
+            let link = format!("https://example.com/{}", i);
+            let mut entry = entry(&link, &[]);
+            entry.timestamp = at(*day);
+            ids.push(add(&db, entry));
+        }
+        // The oldest has been read, so the day 2 entries are next, in order
+        db.mark_as_read(ids[3]).unwrap();
+
+        assert_eq!(db.next_queued().unwrap().unwrap().id, ids[1]);
+        db.mark_as_read(ids[1]).unwrap();
+        assert_eq!(db.next_queued().unwrap().unwrap().id, ids[2]);
+        db.mark_as_read(ids[2]).unwrap();
+        assert_eq!(db.next_queued().unwrap().unwrap().id, ids[0]);
+    }
+
+    #[test]
+    fn delete_by_tag_removes_whole_entries() {
+        let db = Database::open_in_memory().unwrap();
+        let only = add(&db, entry("https://example.com/1", &["topic"]));
+        let also = add(&db, entry("https://example.com/2", &["keep", "Topic"]));
+        let other = add(&db, entry("https://example.com/3", &["keep"]));
+        let untagged = add(&db, entry("https://example.com/4", &[]));
+
+        assert_eq!(db.delete_by_tag("topic"), Ok(2));
+        assert_eq!(db.get_entry(only), Ok(None));
+        assert_eq!(db.get_entry(also), Ok(None));
+        let remaining: Vec<i64> = db.list_entries().unwrap().iter().map(|e| e.id).collect();
+        assert_eq!(remaining, vec![other, untagged]);
+        assert_eq!(db.get_tags_for_link(other).unwrap(), vec!["keep"]);
+
+        // No associations are left behind for the deleted entries
+        let linktags: i64 = db
+            .conn
+            .query_row("select count(*) from linktags", params![], |row| row.get(0))
+            .unwrap();
+        assert_eq!(linktags, 1);
+
+        assert_eq!(db.delete_by_tag("topic"), Ok(0));
+        assert_eq!(db.delete_by_tag("missing"), Ok(0));
+    }
+
+    #[test]
+    fn has_queued_with_tag_until_read() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = add(&db, entry("https://example.com", &["tag 0"]));
+        add(&db, entry("https://example.org", &["tag 1"]));
+
+        assert_eq!(db.has_queued_with_tag("tag 0"), Ok(true));
+        assert_eq!(db.has_queued_with_tag("TAG 0"), Ok(true));
+        assert_eq!(db.has_queued_with_tag("missing"), Ok(false));
+
+        db.mark_as_read(link_id).unwrap();
+        assert_eq!(db.has_queued_with_tag("tag 0"), Ok(false));
+        assert_eq!(db.has_queued_with_tag("tag 1"), Ok(true));
+    }
+
+    #[test]
+    fn migration_merges_tags_differing_in_case() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tsd.db");
+        {
+            // A version 2 database, from when tags were case sensitive
+            let db = Database::open_from_path(&path).unwrap();
+            db.conn
+                .execute_batch(
+                    "
+                    drop index idx_tags_tag_nocase;
+                    drop index idx_linktags_link_tag;
+                    create unique index idx_tags_tag on tags(tag);
+                    insert into links (link, timestamp) values
+                        ('https://example.com/1', '2020-06-02T09:00:00'),
+                        ('https://example.com/2', '2020-06-02T09:00:00');
+                    insert into tags (tag) values ('rust'), ('Rust'), ('go'), ('RUST');
+                    insert into linktags (link_id, tag_id) values (1, 1), (1, 2), (2, 3), (2, 4);
+                    pragma user_version = 2;",
+                )
+                .unwrap();
+        }
+
+        let db = Database::open_from_path(&path).unwrap();
+        assert_eq!(db.list_tags().unwrap(), vec!["go", "rust"]);
+        assert_eq!(db.get_tags_for_link(1).unwrap(), vec!["rust"]);
+        assert_eq!(db.get_tags_for_link(2).unwrap(), vec!["rust", "go"]);
+        assert_eq!(db.count_links_for_tag("Rust"), Ok(2));
+        assert!(db
+            .conn
+            .execute("insert into tags (tag) values ('Go')", params![])
+            .is_err());
+    }
+
+    #[test]
+    fn repeated_tags_linked_once() {
+        let db = Database::open_in_memory().unwrap();
+        let link_id = add(&db, entry("https://example.com", &["rust", "Rust", "rust"]));
+
+        assert_eq!(db.get_tags_for_link(link_id).unwrap(), vec!["rust"]);
+        assert_eq!(db.count_links_for_tag("rust"), Ok(1));
+        assert_eq!(
+            db.list_tags_with_counts().unwrap(),
+            vec![("rust".to_string(), 1)]
+        );
+
+        let tag_id = db.get_or_create_tag("rust").unwrap();
+        assert!(db
+            .conn
+            .execute(
+                "insert into linktags (link_id, tag_id) values (?1, ?2)",
+                params![link_id, tag_id],
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn migration_removes_repeated_linktags() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("tsd.db");
+        {
+            // A version 3 database, where a link could have a tag twice
+            let db = Database::open_from_path(&path).unwrap();
+            db.conn
+                .execute_batch(
+                    "
+                    drop index idx_linktags_link_tag;
+                    insert into links (link, timestamp) values
+                        ('https://example.com', '2020-06-02T09:00:00');
+                    insert into tags (tag) values ('rust');
+                    insert into linktags (link_id, tag_id) values (1, 1), (1, 1);
+                    pragma user_version = 3;",
+                )
+                .unwrap();
+        }
+
+        let db = Database::open_from_path(&path).unwrap();
+        assert_eq!(db.get_tags_for_link(1).unwrap(), vec!["rust"]);
+        assert_eq!(db.count_links_for_tag("rust"), Ok(1));
     }
 }
@@ -0,0 +1,227 @@
+use super::{Entry, EntryAddResult, OwnedEntry, TagAddResult, TagQueryResult};
+use crate::error::Result;
+
+/// Datastore - the core operations on a store of links and tags, independent
+/// of how (or where) they're actually stored.
+pub trait Datastore {
+    /// Get the id of a tag, if it's in the store.
+    fn get_tag_id(&self, tag: &str) -> Result<TagQueryResult>;
+
+    /// # Test if a database contains a tag
+    /// ```
+    /// use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// db.add_tag("tag 0").unwrap();
+    /// let tag_exists = db.contains_tag("tag 0").unwrap();
+    /// assert!(tag_exists);
+    /// ```
+    fn contains_tag(&self, tag: &str) -> Result<bool> {
+        match self.get_tag_id(tag)? {
+            TagQueryResult::TagId(_) => Ok(true),
+            TagQueryResult::TagNotFound => Ok(false),
+        }
+    }
+
+    /// List all of the tags in the store, in alphabetical order.
+    fn list_tags(&self) -> Result<Vec<String>>;
+
+    /// Add a tag to the store. If the tag already exists, this does nothing.
+    fn add_tag(&self, tag: &str) -> Result<TagAddResult>;
+
+    /// Get the id of a link, if it's in the store.
+    fn get_link_id(&self, link: &str) -> Result<Option<i64>>;
+
+    /// # Test if a database contains a link
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// db.add_entry(Entry {
+    ///     link: "https://example.com",
+    ///     comment: None,
+    ///     tags: None,
+    ///     archive: Archive::Queue,
+    ///     timestamp: chrono::Utc::now().naive_utc(),
+    ///     source: None,
+    /// })
+    /// .unwrap();
+    /// assert!(db.contains_link("https://example.com").unwrap());
+    /// assert!(!db.contains_link("https://example.org").unwrap());
+    /// ```
+    fn contains_link(&self, link: &str) -> Result<bool> {
+        Ok(self.get_link_id(link)?.is_some())
+    }
+
+    /// # Add an entry to the database
+    ///
+    /// The entry is tagged with each of its tags, which are created if they
+    /// don't already exist. If the link is already in the database, this
+    /// method does nothing.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let entry = || Entry {
+    ///     link: "https://example.com",
+    ///     comment: None,
+    ///     tags: None,
+    ///     archive: Archive::Queue,
+    ///     timestamp: chrono::Utc::now().naive_utc(),
+    ///     source: None,
+    /// };
+    /// assert_eq!(db.add_entry(entry()), Ok(EntryAddResult::LinkId(1)));
+    /// assert_eq!(db.add_entry(entry()), Ok(EntryAddResult::LinkAlreadyExists));
+    /// ```
+    fn add_entry(&self, entry: Entry) -> Result<EntryAddResult> {
+        if self.contains_link(entry.link)? {
+            Ok(EntryAddResult::LinkAlreadyExists)
+        } else {
+            self.add_entry_allow_duplicate(entry)
+                .map(EntryAddResult::LinkId)
+        }
+    }
+
+    /// Add an entry to the store, even if the link is already in it. Returns
+    /// the id of the new link.
+    fn add_entry_allow_duplicate(&self, entry: Entry) -> Result<i64>;
+
+    /// Get an entry (including its tags) by id.
+    fn get_entry(&self, link_id: i64) -> Result<Option<OwnedEntry>>;
+
+    /// Get the tags associated with a link.
+    fn get_tags_for_link(&self, link_id: i64) -> Result<Vec<String>>;
+
+    /// Delete an entry and its tag associations, returning false if there's
+    /// no entry with the given id.
+    fn delete_entry(&self, link_id: i64) -> Result<bool>;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::datamodel::{Archive, Database};
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+
+    // A trivial store that keeps everything in hash maps, to check that the
+    // trait is all that generic code needs.
+    #[derive(Default)]
+    struct MemStore {
+        links: RefCell<HashMap<i64, OwnedEntry>>,
+        tags: RefCell<HashMap<String, i64>>,
+    }
+
+    impl Datastore for MemStore {
+        fn get_tag_id(&self, tag: &str) -> Result<TagQueryResult> {
+            Ok(match self.tags.borrow().get(tag) {
+                Some(i) => TagQueryResult::TagId(*i),
+                None => TagQueryResult::TagNotFound,
+            })
+        }
+
+        fn list_tags(&self) -> Result<Vec<String>> {
+            let mut tags: Vec<String> = self.tags.borrow().keys().cloned().collect();
+            tags.sort();
+            Ok(tags)
+        }
+
+        fn add_tag(&self, tag: &str) -> Result<TagAddResult> {
+            if self.contains_tag(tag)? {
+                return Ok(TagAddResult::TagAlreadyExists);
+            }
+            let mut tags = self.tags.borrow_mut();
+            let tag_id = tags.len() as i64 + 1;
+            tags.insert(tag.to_string(), tag_id);
+            Ok(TagAddResult::TagId(tag_id))
+        }
+
+        fn get_link_id(&self, link: &str) -> Result<Option<i64>> {
+            let links = self.links.borrow();
+            Ok(links
+                .values()
+                .filter(|e| e.link == link)
+                .map(|e| e.id)
+                .min())
+        }
+
+        fn add_entry_allow_duplicate(&self, entry: Entry) -> Result<i64> {
+            let tags: Vec<&str> = entry.tags.unwrap_or_default();
+            for tag in &tags {
+                self.add_tag(tag)?;
+            }
+            let mut links = self.links.borrow_mut();
+            let link_id = links.keys().max().unwrap_or(&0) + 1;
+            links.insert(
+                link_id,
+                OwnedEntry {
+                    id: link_id,
+                    link: entry.link.to_string(),
+                    comment: entry.comment.map(|c| c.to_string()),
+                    tags: tags.iter().map(|t| t.to_string()).collect(),
+                    archive: entry.archive,
+                    timestamp: entry.timestamp,
+                    source: entry.source.map(|s| s.to_string()),
+                },
+            );
+            Ok(link_id)
+        }
+
+        fn get_entry(&self, link_id: i64) -> Result<Option<OwnedEntry>> {
+            Ok(self.links.borrow().get(&link_id).cloned())
+        }
+
+        fn get_tags_for_link(&self, link_id: i64) -> Result<Vec<String>> {
+            Ok(self.get_entry(link_id)?.map(|e| e.tags).unwrap_or_default())
+        }
+
+        fn delete_entry(&self, link_id: i64) -> Result<bool> {
+            Ok(self.links.borrow_mut().remove(&link_id).is_some())
+        }
+    }
+
+    // Exercise a store purely through the trait.
+    fn exercise<D: Datastore>(store: &D) {
+        let entry = || Entry {
+            link: "https://example.com",
+            comment: Some("An example"),
+            tags: Some(vec!["tag 0", "tag 1"]),
+            archive: Archive::Queue,
+            timestamp: chrono::Utc::now().naive_utc(),
+            source: None,
+        };
+        let link_id = match store.add_entry(entry()).unwrap() {
+            EntryAddResult::LinkAlreadyExists => panic!("link should not already exist!"),
+            EntryAddResult::LinkId(i) => i,
+        };
+        assert_eq!(
+            store.add_entry(entry()),
+            Ok(EntryAddResult::LinkAlreadyExists)
+        );
+        assert!(store.contains_link("https://example.com").unwrap());
+        assert_eq!(store.get_link_id("https://example.com"), Ok(Some(link_id)));
+
+        assert_eq!(store.list_tags().unwrap(), vec!["tag 0", "tag 1"]);
+        assert!(store.contains_tag("tag 1").unwrap());
+        assert_eq!(store.add_tag("tag 1"), Ok(TagAddResult::TagAlreadyExists));
+        assert_eq!(
+            store.get_tags_for_link(link_id).unwrap(),
+            vec!["tag 0", "tag 1"]
+        );
+
+        let stored = store.get_entry(link_id).unwrap().unwrap();
+        assert_eq!(stored.link, "https://example.com");
+        assert_eq!(stored.comment, Some("An example".to_string()));
+
+        assert!(store.delete_entry(link_id).unwrap());
+        assert_eq!(store.get_entry(link_id), Ok(None));
+        assert!(!store.contains_link("https://example.com").unwrap());
+    }
+
+    #[test]
+    fn memstore_satisfies_datastore() {
+        exercise(&MemStore::default());
+    }
+
+    #[test]
+    fn database_satisfies_datastore() {
+        exercise(&Database::open_in_memory().unwrap());
+    }
+}
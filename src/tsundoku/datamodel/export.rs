@@ -0,0 +1,257 @@
+use super::{Archive, Database};
+use crate::error::Result;
+
+use serde::ser::{SerializeSeq, Serializer};
+
+use std::io::Write;
+
+// Exporting the pile to other formats, e.g. for backups or other tools. The
+// entries are written as they're read, so that exporting a big pile doesn't
+// need it all in memory at once.
+impl Database {
+    /// # Export the pile as JSON
+    ///
+    /// Writes every entry to `writer` as a JSON array of objects, with the
+    /// archive state as a lowercase string (e.g. `"queue"`).
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let mut json = vec![];
+    /// db.export_json(&mut json).unwrap();
+    /// assert_eq!(json, b"[]");
+    /// ```
+    pub fn export_json<W: Write>(&self, writer: W) -> Result<()> {
+        let mut serializer = serde_json::Serializer::new(writer);
+        let mut entries = serializer.serialize_seq(None)?;
+        self.for_each_entry(|entry| Ok(entries.serialize_element(&entry)?))?;
+        Ok(entries.end()?)
+    }
+
+    /// # Export the pile as browser bookmarks
+    ///
+    /// Writes every entry to `writer` in the Netscape bookmark file format,
+    /// which most browsers can import. Entries are titled with their comment
+    /// (or the link itself, if there isn't one), and tags are kept in the
+    /// `TAGS` attribute.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let mut html = vec![];
+    /// db.export_netscape(&mut html).unwrap();
+    /// assert!(String::from_utf8(html).unwrap().starts_with("<!DOCTYPE NETSCAPE-Bookmark-file-1>"));
+    /// ```
+    pub fn export_netscape<W: Write>(&self, mut writer: W) -> Result<()> {
+        writeln!(writer, "<!DOCTYPE NETSCAPE-Bookmark-file-1>")?;
+        writeln!(
+            writer,
+            "<META HTTP-EQUIV=\"Content-Type\" CONTENT=\"text/html; charset=UTF-8\">"
+        )?;
+        writeln!(writer, "<TITLE>Bookmarks</TITLE>")?;
+        writeln!(writer, "<H1>Bookmarks</H1>")?;
+        writeln!(writer, "<DL><p>")?;
+        self.for_each_entry(|entry| {
+            write!(
+                writer,
+                "    <DT><A HREF=\"{}\" ADD_DATE=\"{}\"",
+                escape_html(&entry.link),
+                entry.timestamp.and_utc().timestamp()
+            )?;
+            if !entry.tags.is_empty() {
+                write!(writer, " TAGS=\"{}\"", escape_html(&entry.tags.join(",")))?;
+            }
+            writeln!(
+                writer,
+                ">{}</A>",
+                escape_html(entry.comment.as_ref().unwrap_or(&entry.link))
+            )?;
+            Ok(())
+        })?;
+        writeln!(writer, "</DL><p>")?;
+        Ok(())
+    }
+
+    /// # Export the pile as Markdown
+    ///
+    /// Writes the entries to `writer` as bulleted lists of links, with the
+    /// entries that have been read under a "Read" heading, and everything
+    /// else under a "Queue" heading. Each entry is titled with its comment
+    /// (or the link itself, if there isn't one), followed by its tags.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let entry = EntryBuilder::new().link("https://example.com").tag("tag 0").build().unwrap();
+    /// db.add_entry(entry).unwrap();
+    /// let mut markdown = vec![];
+    /// db.export_markdown(&mut markdown).unwrap();
+    /// assert_eq!(
+    ///     String::from_utf8(markdown).unwrap(),
+    ///     "## Queue\n\n- [https://example.com](https://example.com) (tags: tag 0)\n\n## Read\n\n"
+    /// );
+    /// ```
+    pub fn export_markdown<W: Write>(&self, mut writer: W) -> Result<()> {
+        let sections = [
+            ("Queue", "where archive != ?1 order by link_id"),
+            ("Read", "where archive == ?1 order by link_id"),
+        ];
+        for &(heading, clause) in &sections {
+            writeln!(writer, "## {}\n", heading)?;
+            let mut any = false;
+            self.visit_entries(clause, params![Archive::Archive as u8], |entry| {
+                any = true;
+                write!(
+                    writer,
+                    "- [{}]({})",
+                    escape_markdown(entry.comment.as_ref().unwrap_or(&entry.link)),
+                    escape_markdown(&entry.link)
+                )?;
+                if !entry.tags.is_empty() {
+                    write!(writer, " (tags: {})", entry.tags.join(", "))?;
+                }
+                writeln!(writer)?;
+                Ok(())
+            })?;
+            if any {
+                writeln!(writer)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Escape text so that it can go in HTML, either as content or an attribute.
+fn escape_html(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+// Escape the characters that would end a Markdown link's text or target early.
+fn escape_markdown(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if let '\\' | '[' | ']' | '(' | ')' = c {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod test {
+    use crate::datamodel::*;
+    use serde_json::Value;
+
+    #[test]
+    fn export_json_shape() {
+        let db = Database::open_in_memory().unwrap();
+        let queued = EntryBuilder::new()
+            .link("https://example.com")
+            .comment("An example")
+            .tags(vec!["tag 0", "tag 1"])
+            .build()
+            .unwrap();
+        db.add_entry(queued).unwrap();
+        let read = EntryBuilder::new()
+            .link("https://example.org")
+            .build()
+            .unwrap();
+        db.add_entry(read).unwrap();
+        db.mark_as_read(2).unwrap();
+
+        let mut json = vec![];
+        db.export_json(&mut json).unwrap();
+        let exported: Value = serde_json::from_slice(&json).unwrap();
+        let entries = exported.as_array().unwrap();
+        assert_eq!(entries.len(), 2);
+
+        assert_eq!(entries[0]["id"], 1);
+        assert_eq!(entries[0]["link"], "https://example.com");
+        assert_eq!(entries[0]["comment"], "An example");
+        assert_eq!(entries[0]["tags"], serde_json::json!(["tag 0", "tag 1"]));
+        assert_eq!(entries[0]["archive"], "queue");
+        assert!(entries[0]["timestamp"].is_string());
+
+        assert_eq!(entries[1]["link"], "https://example.org");
+        assert_eq!(entries[1]["comment"], Value::Null);
+        assert_eq!(entries[1]["tags"], serde_json::json!([]));
+        assert_eq!(entries[1]["archive"], "archive");
+    }
+
+    #[test]
+    fn export_netscape_anchors() {
+        let db = Database::open_in_memory().unwrap();
+        let timestamp = chrono::NaiveDate::from_ymd_opt(2020, 6, 2)
+            .unwrap()
+            .and_hms_opt(9, 0, 0)
+            .unwrap();
+        let tagged = EntryBuilder::new()
+            .link("https://example.com/?a=1&b=2")
+            .comment("Ampersands & <brackets>")
+            .tags(vec!["tag 0", "tag 1"])
+            .timestamp(timestamp)
+            .build()
+            .unwrap();
+        db.add_entry(tagged).unwrap();
+        let untagged = EntryBuilder::new()
+            .link("https://example.org")
+            .timestamp(timestamp)
+            .build()
+            .unwrap();
+        db.add_entry(untagged).unwrap();
+
+        let mut html = vec![];
+        db.export_netscape(&mut html).unwrap();
+        let html = String::from_utf8(html).unwrap();
+
+        assert!(html.starts_with("<!DOCTYPE NETSCAPE-Bookmark-file-1>\n"));
+        assert!(html.contains(
+            "<DT><A HREF=\"https://example.com/?a=1&amp;b=2\" ADD_DATE=\"1591088400\" \
+             TAGS=\"tag 0,tag 1\">Ampersands &amp; &lt;brackets&gt;</A>\n"
+        ));
+        assert!(html.contains(
+            "<DT><A HREF=\"https://example.org\" ADD_DATE=\"1591088400\">https://example.org</A>\n"
+        ));
+        assert!(html.trim_end().ends_with("</DL><p>"));
+    }
+
+    #[test]
+    fn export_markdown_headings() {
+        let db = Database::open_in_memory().unwrap();
+        let read = EntryBuilder::new()
+            .link("https://example.com")
+            .comment("An [example]")
+            .build()
+            .unwrap();
+        db.add_entry(read).unwrap();
+        db.mark_as_read(1).unwrap();
+        let queued = EntryBuilder::new()
+            .link("https://example.org")
+            .tags(vec!["tag 0", "tag 1"])
+            .build()
+            .unwrap();
+        db.add_entry(queued).unwrap();
+
+        let mut markdown = vec![];
+        db.export_markdown(&mut markdown).unwrap();
+        assert_eq!(
+            String::from_utf8(markdown).unwrap(),
+            "## Queue\n\
+             \n\
+             - [https://example.org](https://example.org) (tags: tag 0, tag 1)\n\
+             \n\
+             ## Read\n\
+             \n\
+             - [An \\[example\\]](https://example.com)\n\
+             \n"
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use super::{Database, Datastore, Entry, EntryAddResult};
+use crate::error::Result;
+
+use std::time::Duration;
+
+// How long to wait for a page before giving up on its title.
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Filling in comments from the web, with the "fetch-title" feature.
+impl Database {
+    /// # Add an entry, titled from the web
+    ///
+    /// Like `add_entry`, but if the entry has no comment, the link is
+    /// fetched and the page's `<title>` is used as the comment instead. If
+    /// the page can't be fetched, or doesn't have a title, the entry is
+    /// added without a comment.
+    pub fn add_entry_fetch_title(&self, entry: Entry) -> Result<EntryAddResult> {
+        if entry.comment.is_some() || self.contains_link(entry.link)? {
+            return self.add_entry(entry);
+        }
+        let title = fetch_title(entry.link);
+        self.add_entry(Entry {
+            comment: title.as_deref(),
+            ..entry
+        })
+    }
+}
+
+// Fetch a page, and pull out its title, if we can.
+fn fetch_title(link: &str) -> Option<String> {
+    let agent = ureq::AgentBuilder::new().timeout(FETCH_TIMEOUT).build();
+    let page = agent.get(link).call().ok()?.into_string().ok()?;
+    extract_title(&page)
+}
+
+// Find the contents of the first `<title>` element in some HTML, tidied up
+// for use as a comment.
+fn extract_title(html: &str) -> Option<String> {
+    // Only ASCII is lowercased, so byte offsets are the same in both strings
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+
+    let title = unescape_html(&html[start..end])
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ");
+    if title.is_empty() {
+        None
+    } else {
+        Some(title)
+    }
+}
+
+// Decode the handful of HTML entities that commonly turn up in titles.
+fn unescape_html(s: &str) -> String {
+    s.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&#x27;", "'")
+        .replace("&nbsp;", " ")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::datamodel::EntryBuilder;
+
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    // Serve a single HTTP response with the given body, returning the URL
+    // to fetch it from.
+    fn serve_once(body: &'static str) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/page", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0; 1024];
+            let _ = stream.read(&mut request).unwrap();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .unwrap();
+        });
+        url
+    }
+
+    #[test]
+    fn fetch_title_as_comment() {
+        let url = serve_once(
+            "<html><head><TITLE>\n  Ferris &amp; friends\n</TITLE></head><body></body></html>",
+        );
+        let db = Database::open_in_memory().unwrap();
+        let entry = EntryBuilder::new().link(&url).build().unwrap();
+        let link_id = match db.add_entry_fetch_title(entry).unwrap() {
+            EntryAddResult::LinkId(i) => i,
+            EntryAddResult::LinkAlreadyExists => panic!("link should not already exist!"),
+        };
+
+        assert_eq!(
+            db.get_entry(link_id).unwrap().unwrap().comment,
+            Some("Ferris & friends".to_string())
+        );
+    }
+
+    #[test]
+    fn fetch_title_failure_adds_without_comment() {
+        // Bind and immediately drop a listener, so nothing is serving there
+        let url = {
+            let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/page", listener.local_addr().unwrap())
+        };
+        let untitled = serve_once("<html><body>No title here</body></html>");
+
+        let db = Database::open_in_memory().unwrap();
+        for link in &[url, untitled] {
+            let entry = EntryBuilder::new().link(link).build().unwrap();
+            assert!(matches!(
+                db.add_entry_fetch_title(entry),
+                Ok(EntryAddResult::LinkId(_))
+            ));
+        }
+        let entries = db.list_entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(entries.iter().all(|e| e.comment.is_none()));
+    }
+
+    #[test]
+    fn extract_title_edge_cases() {
+        assert_eq!(
+            extract_title("<title lang=\"en\">A  &lt;b&gt;\ttitle</title>"),
+            Some("A <b> title".to_string())
+        );
+        assert_eq!(extract_title("<title>   </title>"), None);
+        assert_eq!(extract_title("<title>Unclosed"), None);
+        assert_eq!(extract_title("<p>No title</p>"), None);
+    }
+}
@@ -0,0 +1,443 @@
+use super::{Archive, Database, Datastore, Entry, EntryAddResult, EntryBuilder};
+use crate::error::Result;
+
+use chrono::naive::NaiveDateTime;
+use serde::Deserialize;
+
+use std::io::{BufRead, BufReader, Read};
+
+// An entry as written by `export_json`. Any id is ignored, as the entry gets
+// a new one when it's added, and the tags, archive state and source are
+// optional.
+#[derive(Deserialize)]
+struct JsonEntry {
+    link: String,
+    comment: Option<String>,
+    #[serde(default)]
+    tags: Vec<String>,
+    #[serde(default)]
+    archive: Archive,
+    timestamp: NaiveDateTime,
+    source: Option<String>,
+}
+
+// A row of a CSV import, with the tags separated by semicolons.
+#[derive(Deserialize)]
+struct CsvEntry {
+    link: String,
+    comment: Option<String>,
+    tags: Option<String>,
+}
+
+// Importing entries into the pile from other formats.
+impl Database {
+    /// # Import entries from JSON
+    ///
+    /// Reads a JSON array of entries, in the format written by `export_json`,
+    /// and adds each of them to the database. Links that are already in the
+    /// database are skipped. Returns the number of entries added.
+    ///
+    /// The `id` of each entry is ignored, and `tags`, `archive` and `source`
+    /// can be left out, in which case the entry has no tags, is put in the
+    /// queue, and has `"json"` as its source.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let json = r#"[{"link": "https://example.com", "comment": null,
+    ///                 "timestamp": "2020-06-02T09:00:00"}]"#;
+    /// assert_eq!(db.import_json(json.as_bytes()), Ok(1));
+    /// assert_eq!(db.import_json(json.as_bytes()), Ok(0));
+    /// ```
+    pub fn import_json<R: Read>(&self, reader: R) -> Result<usize> {
+        // Parse everything up front, so that malformed JSON doesn't leave us
+        // with half an import.
+        let entries: Vec<JsonEntry> = serde_json::from_reader(reader)?;
+
+        let mut added = 0;
+        for entry in &entries {
+            if let EntryAddResult::LinkId(_) = self.add_entry(Entry {
+                link: &entry.link,
+                comment: entry.comment.as_deref(),
+                tags: Some(entry.tags.iter().map(String::as_str).collect()),
+                archive: entry.archive,
+                timestamp: entry.timestamp,
+                source: entry.source.as_deref().or(Some("json")),
+            })? {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// # Import entries from CSV
+    ///
+    /// Reads a CSV file with a header row of `link,comment,tags`, where the
+    /// tags are separated by semicolons, and adds an entry for each row.
+    /// Rows without a link, and links already in the database, are skipped.
+    /// Returns the number of entries added, each with `"csv"` as its source.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let csv = "link,comment,tags\nhttps://example.com,An example,tag 0;tag 1\n";
+    /// assert_eq!(db.import_csv(csv.as_bytes()), Ok(1));
+    /// ```
+    pub fn import_csv<R: Read>(&self, reader: R) -> Result<usize> {
+        let mut reader = csv::Reader::from_reader(reader);
+
+        let mut added = 0;
+        for row in reader.deserialize() {
+            let row: CsvEntry = row?;
+            if row.link.trim().is_empty() {
+                continue;
+            }
+
+            let mut builder = EntryBuilder::new().link(row.link.trim()).source("csv");
+            if let Some(comment) = &row.comment {
+                builder = builder.comment(comment);
+            }
+            if let Some(tags) = &row.tags {
+                builder = builder.tags(tags.split(';').map(str::trim).filter(|t| !t.is_empty()));
+            }
+
+            if let EntryAddResult::LinkId(_) = self.add_entry(builder.build()?)? {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+
+    /// # Import entries from OPML
+    ///
+    /// Reads an OPML document (e.g. the feeds exported from an RSS reader)
+    /// and adds an entry for each `<outline>` with an `xmlUrl` (or failing
+    /// that, an `htmlUrl`), commented with its `title` or `text`. Outlines
+    /// without a link are treated as folders, and the entries inside them
+    /// are tagged with the folder names. Links already in the database are
+    /// skipped. Returns the number of entries added, each with `"opml"` as
+    /// its source.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let opml = r#"<opml version="2.0"><body>
+    ///     <outline text="Example" xmlUrl="https://example.com/feed.xml"/>
+    /// </body></opml>"#;
+    /// assert_eq!(db.import_opml(opml.as_bytes()), Ok(1));
+    /// ```
+    pub fn import_opml<R: Read>(&self, mut reader: R) -> Result<usize> {
+        let mut contents = String::new();
+        reader.read_to_string(&mut contents)?;
+        let document = roxmltree::Document::parse(&contents)?;
+
+        let mut added = 0;
+        let mut folders = vec![];
+        for body in document
+            .root_element()
+            .children()
+            .filter(|n| n.has_tag_name("body"))
+        {
+            added += self.import_outlines(body, &mut folders)?;
+        }
+        Ok(added)
+    }
+
+    // Add the entries for the outlines under `node`, tagging them with the
+    // names of the folders that they're in.
+    fn import_outlines<'a>(
+        &self,
+        node: roxmltree::Node<'a, '_>,
+        folders: &mut Vec<&'a str>,
+    ) -> Result<usize> {
+        let mut added = 0;
+        for outline in node.children().filter(|n| n.has_tag_name("outline")) {
+            let name = outline
+                .attribute("title")
+                .or_else(|| outline.attribute("text"));
+            match outline
+                .attribute("xmlUrl")
+                .or_else(|| outline.attribute("htmlUrl"))
+                .map(str::trim)
+                .filter(|l| !l.is_empty())
+            {
+                Some(link) => {
+                    let mut builder = EntryBuilder::new()
+                        .link(link)
+                        .tags(folders.iter().cloned())
+                        .source("opml");
+                    if let Some(name) = name.filter(|n| !n.is_empty()) {
+                        builder = builder.comment(name);
+                    }
+                    if let EntryAddResult::LinkId(_) = self.add_entry(builder.build()?)? {
+                        added += 1;
+                    }
+                }
+                None => {
+                    let folder = name.map(str::trim).filter(|n| !n.is_empty());
+                    folders.extend(folder);
+                    added += self.import_outlines(outline, folders)?;
+                    if folder.is_some() {
+                        folders.pop();
+                    }
+                }
+            }
+        }
+        Ok(added)
+    }
+
+    /// # Add entries from a list of links
+    ///
+    /// Reads one link per line, ignoring blank lines and whitespace around
+    /// each link, and adds each to the queue tagged with `tags`, with `source`
+    /// as its source (e.g. `"stdin"`). A link that
+    /// can't be added doesn't stop the rest, so each link is returned along
+    /// with what happened when we tried to add it. Fails only if the lines
+    /// themselves can't be read.
+    /// ```
+    /// # use tsundoku::datamodel::*;
+    /// let db = Database::open_in_memory().unwrap();
+    /// let lines = "https://example.com\n\nhttps://example.com\n";
+    /// let results = db.add_entries_from_lines(lines.as_bytes(), &["tag 0"], "lines").unwrap();
+    /// assert_eq!(results.len(), 2);
+    /// assert_eq!(results[0].1, Ok(EntryAddResult::LinkId(1)));
+    /// assert_eq!(results[1].1, Ok(EntryAddResult::LinkAlreadyExists));
+    /// ```
+    pub fn add_entries_from_lines<R: Read>(
+        &self,
+        reader: R,
+        tags: &[&str],
+        source: &str,
+    ) -> Result<Vec<(String, Result<EntryAddResult>)>> {
+        let mut results = vec![];
+        for line in BufReader::new(reader).lines() {
+            let line = line?;
+            let link = line.trim();
+            if link.is_empty() {
+                continue;
+            }
+
+            let added = EntryBuilder::new()
+                .link(link)
+                .tags(tags.iter().cloned())
+                .source(source)
+                .build()
+                .and_then(|entry| self.add_entry(entry));
+            results.push((link.to_string(), added));
+        }
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::datamodel::*;
+    use crate::error::TsundokuError;
+
+    #[test]
+    fn import_json_round_trip() {
+        let db = Database::open_in_memory().unwrap();
+        let entries = vec![
+            EntryBuilder::new()
+                .link("https://example.com")
+                .comment("An example")
+                .tags(vec!["tag 0", "tag 1"])
+                .build()
+                .unwrap(),
+            EntryBuilder::new()
+                .link("https://example.org")
+                .tag("tag 1")
+                .build()
+                .unwrap(),
+            EntryBuilder::new()
+                .link("https://example.net")
+                .build()
+                .unwrap(),
+        ];
+        for entry in entries {
+            db.add_entry(entry).unwrap();
+        }
+        db.set_archive(2, Archive::ReReadLater).unwrap();
+
+        let mut json = vec![];
+        db.export_json(&mut json).unwrap();
+
+        let imported = Database::open_in_memory().unwrap();
+        assert_eq!(imported.import_json(&json[..]), Ok(3));
+        // Everything is the same, except that entries without a source now
+        // have one
+        let mut expected = db.list_entries().unwrap();
+        for entry in &mut expected {
+            entry.source = Some("json".to_string());
+        }
+        assert_eq!(imported.list_entries(), Ok(expected));
+        assert_eq!(imported.list_tags(), db.list_tags());
+
+        // Importing again shouldn't add anything
+        assert_eq!(imported.import_json(&json[..]), Ok(0));
+        assert_eq!(imported.count_entries(), Ok(3));
+    }
+
+    #[test]
+    fn import_json_malformed() {
+        let db = Database::open_in_memory().unwrap();
+        let json = r#"[{"link": "https://example.com", "timestamp": "2020-06-02T09:00:00"},
+                       {"link": "https://example.org"}]"#;
+        assert!(matches!(
+            db.import_json(json.as_bytes()),
+            Err(TsundokuError::Json(_))
+        ));
+        assert_eq!(db.count_entries(), Ok(0));
+    }
+
+    #[test]
+    fn import_csv_entries() {
+        let db = Database::open_in_memory().unwrap();
+        let csv = "\
+link,comment,tags
+https://example.com,\"An example, with a comma\",tag 0; tag 1
+,A row without a link,tag 2
+https://example.org,,
+https://example.com,A duplicate,tag 3
+";
+        assert_eq!(db.import_csv(csv.as_bytes()), Ok(2));
+
+        let entries = db.list_entries().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].link, "https://example.com");
+        assert_eq!(
+            entries[0].comment,
+            Some("An example, with a comma".to_string())
+        );
+        assert_eq!(entries[0].tags, vec!["tag 0", "tag 1"]);
+        assert_eq!(entries[1].link, "https://example.org");
+        assert_eq!(entries[1].comment, None);
+        assert!(entries[1].tags.is_empty());
+        assert_eq!(db.list_tags().unwrap(), vec!["tag 0", "tag 1"]);
+    }
+
+    #[test]
+    fn add_entries_from_lines_skips_blanks_and_duplicates() {
+        let db = Database::open_in_memory().unwrap();
+        db.add_entry(
+            EntryBuilder::new()
+                .link("https://example.net")
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+        let lines = "\
+https://example.com
+   https://example.org\t
+
+https://example.com
+https://example.net
+";
+        let results = db
+            .add_entries_from_lines(lines.as_bytes(), &["tag 0", "tag 1"], "test")
+            .unwrap();
+
+        let added: Vec<&str> = results
+            .iter()
+            .filter(|(_, r)| matches!(r, Ok(EntryAddResult::LinkId(_))))
+            .map(|(link, _)| link.as_str())
+            .collect();
+        assert_eq!(added, vec!["https://example.com", "https://example.org"]);
+        assert_eq!(results.len(), 4);
+        assert_eq!(results[2].1, Ok(EntryAddResult::LinkAlreadyExists));
+        assert_eq!(results[3].1, Ok(EntryAddResult::LinkAlreadyExists));
+
+        assert_eq!(db.count_entries(), Ok(3));
+        for entry in db.get_links_for_tag("tag 1").unwrap() {
+            assert_eq!(entry.tags, vec!["tag 0", "tag 1"]);
+        }
+        assert_eq!(db.get_links_for_tag("tag 1").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn import_opml_folders_as_tags() {
+        let db = Database::open_in_memory().unwrap();
+        let opml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<opml version="2.0">
+  <head><title>Subscriptions</title></head>
+  <body>
+    <outline text="Loose feed" xmlUrl="https://example.com/feed.xml" htmlUrl="https://example.com"/>
+    <outline text="Programming" title="Programming">
+      <outline title="This Week in Rust" text="TWiR" xmlUrl="https://this-week-in-rust.org/rss.xml"/>
+      <outline text="Languages">
+        <outline text="Go Blog" htmlUrl="https://go.dev/blog"/>
+      </outline>
+    </outline>
+    <outline text="Empty folder"/>
+  </body>
+</opml>"#;
+        assert_eq!(db.import_opml(opml.as_bytes()), Ok(3));
+        assert_eq!(db.import_opml(opml.as_bytes()), Ok(0));
+
+        let entries = db.list_entries().unwrap();
+        assert_eq!(entries[0].link, "https://example.com/feed.xml");
+        assert_eq!(entries[0].comment, Some("Loose feed".to_string()));
+        assert!(entries[0].tags.is_empty());
+        assert_eq!(entries[1].link, "https://this-week-in-rust.org/rss.xml");
+        assert_eq!(entries[1].comment, Some("This Week in Rust".to_string()));
+        assert_eq!(entries[1].tags, vec!["Programming"]);
+        assert_eq!(entries[2].link, "https://go.dev/blog");
+        assert_eq!(entries[2].tags, vec!["Programming", "Languages"]);
+    }
+
+    #[test]
+    fn import_opml_malformed() {
+        let db = Database::open_in_memory().unwrap();
+        let opml = r#"<opml><body><outline xmlUrl="https://example.com"></body></opml>"#;
+        assert!(matches!(
+            db.import_opml(opml.as_bytes()),
+            Err(TsundokuError::Xml(_))
+        ));
+        assert_eq!(db.count_entries(), Ok(0));
+    }
+
+    #[test]
+    fn imports_stamp_source() {
+        let db = Database::open_in_memory().unwrap();
+        db.add_entry(
+            EntryBuilder::new()
+                .link("https://example.net")
+                .build()
+                .unwrap(),
+        )
+        .unwrap();
+        let csv = "link,comment,tags\nhttps://example.com,,\n";
+        db.import_csv(csv.as_bytes()).unwrap();
+        let opml = r#"<opml><body><outline text="Feed" xmlUrl="https://example.org/feed.xml"/></body></opml>"#;
+        db.import_opml(opml.as_bytes()).unwrap();
+        db.add_entries_from_lines("https://example.edu\n".as_bytes(), &[], "stdin")
+            .unwrap();
+
+        let sources: Vec<Option<String>> = db
+            .list_entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.source)
+            .collect();
+        assert_eq!(
+            sources,
+            vec![
+                None,
+                Some("csv".to_string()),
+                Some("opml".to_string()),
+                Some("stdin".to_string())
+            ]
+        );
+
+        // Sources survive an export and import, and are filled in if missing
+        let mut json = vec![];
+        db.export_json(&mut json).unwrap();
+        let copy = Database::open_in_memory().unwrap();
+        copy.import_json(json.as_slice()).unwrap();
+        let copied: Vec<Option<String>> = copy
+            .list_entries()
+            .unwrap()
+            .into_iter()
+            .map(|e| e.source)
+            .collect();
+        assert_eq!(copied[0], Some("json".to_string()));
+        assert_eq!(copied[1..], sources[1..]);
+    }
+}
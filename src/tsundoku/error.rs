@@ -0,0 +1,78 @@
+use std::error;
+use std::fmt;
+use std::io;
+
+/// TsundokuError - Everything that can go wrong while working with the pile.
+#[derive(PartialEq, Debug)]
+pub enum TsundokuError {
+    Sqlite(rusqlite::Error), // The database itself failed
+    NotFound,                // Something we expected to be in the database wasn't
+    DuplicateTag(String),    // A tag that's already in the database
+    MissingLink,             // An entry was built without a link
+    InvalidUrl(String),      // A link that isn't a valid URL
+    Json(String),            // Entries couldn't be written or read as JSON
+    Io(String),              // Reading or writing an import/export failed
+    Csv(String),             // Entries couldn't be read as CSV
+    Xml(String),             // Entries couldn't be read as XML (e.g. OPML)
+    BadTimestamp(String),    // A timestamp in the database that we couldn't read
+    EmptyTag,                // A tag with nothing (but whitespace) in it
+}
+
+pub type Result<T> = std::result::Result<T, TsundokuError>;
+
+impl fmt::Display for TsundokuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TsundokuError::Sqlite(e) => write!(f, "database error: {}", e),
+            TsundokuError::NotFound => write!(f, "not found in the database"),
+            TsundokuError::DuplicateTag(tag) => write!(f, "tag \"{}\" already exists", tag),
+            TsundokuError::MissingLink => write!(f, "an entry needs a link"),
+            TsundokuError::InvalidUrl(link) => write!(f, "\"{}\" isn't a valid URL", link),
+            TsundokuError::Json(e) => write!(f, "JSON error: {}", e),
+            TsundokuError::Io(e) => write!(f, "IO error: {}", e),
+            TsundokuError::Csv(e) => write!(f, "CSV error: {}", e),
+            TsundokuError::Xml(e) => write!(f, "XML error: {}", e),
+            TsundokuError::BadTimestamp(t) => write!(f, "\"{}\" isn't a valid timestamp", t),
+            TsundokuError::EmptyTag => write!(f, "a tag can't be empty"),
+        }
+    }
+}
+
+impl error::Error for TsundokuError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            TsundokuError::Sqlite(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<rusqlite::Error> for TsundokuError {
+    fn from(e: rusqlite::Error) -> TsundokuError {
+        TsundokuError::Sqlite(e)
+    }
+}
+
+impl From<serde_json::Error> for TsundokuError {
+    fn from(e: serde_json::Error) -> TsundokuError {
+        TsundokuError::Json(e.to_string())
+    }
+}
+
+impl From<io::Error> for TsundokuError {
+    fn from(e: io::Error) -> TsundokuError {
+        TsundokuError::Io(e.to_string())
+    }
+}
+
+impl From<csv::Error> for TsundokuError {
+    fn from(e: csv::Error) -> TsundokuError {
+        TsundokuError::Csv(e.to_string())
+    }
+}
+
+impl From<roxmltree::Error> for TsundokuError {
+    fn from(e: roxmltree::Error) -> TsundokuError {
+        TsundokuError::Xml(e.to_string())
+    }
+}
@@ -1,5 +1,8 @@
 #[macro_use]
+extern crate log;
+#[macro_use]
 extern crate rusqlite;
 extern crate chrono;
 
 pub mod datamodel;
+pub mod error;
@@ -0,0 +1,224 @@
+// Tests that run the tsd binary itself, against a database in a temporary
+// directory.
+
+use serde_json::Value;
+use tsundoku::datamodel::{Database, Datastore, EntryBuilder};
+
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Run tsd with the given arguments, using a config that points at a database
+// in `dir`, and get what it printed.
+fn tsd(dir: &Path, args: &[&str]) -> String {
+    let config = dir.join("config.toml");
+    if !config.exists() {
+        let database = dir.join("tsd.db");
+        fs::write(&config, format!("database_path = {:?}\n", database)).unwrap();
+    }
+
+    let output = Command::new(env!("CARGO_BIN_EXE_tsd"))
+        .arg("--config")
+        .arg(&config)
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "tsd {:?} failed: {}",
+        args,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+#[test]
+fn json_list() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(tsd(dir.path(), &["--json", "list"]).trim(), "[]");
+
+    tsd(
+        dir.path(),
+        &["add", "https://example.com", "-t", "tag 0, tag 1"],
+    );
+    tsd(
+        dir.path(),
+        &["add", "https://example.org", "-c", "An example"],
+    );
+
+    let listed: Value = serde_json::from_str(&tsd(dir.path(), &["--json", "list"])).unwrap();
+    let entries = listed.as_array().unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0]["link"], "https://example.com");
+    assert_eq!(entries[0]["tags"], serde_json::json!(["tag 0", "tag 1"]));
+    assert_eq!(entries[1]["comment"], "An example");
+    assert_eq!(entries[1]["archive"], "queue");
+}
+
+#[test]
+fn json_stats_and_bored() {
+    let dir = tempfile::tempdir().unwrap();
+    assert_eq!(tsd(dir.path(), &["--json", "bored"]).trim(), "null");
+
+    tsd(dir.path(), &["add", "https://example.com"]);
+    let stats: Value = serde_json::from_str(&tsd(dir.path(), &["--json", "stats"])).unwrap();
+    assert_eq!(stats["total"], 1);
+    assert_eq!(stats["queued"], 1);
+    assert_eq!(stats["archived"], 0);
+
+    let bored: Value = serde_json::from_str(&tsd(dir.path(), &["--json", "bored"])).unwrap();
+    assert_eq!(bored["link"], "https://example.com");
+}
+
+#[test]
+fn search_with_tag() {
+    let dir = tempfile::tempdir().unwrap();
+    tsd(
+        dir.path(),
+        &["add", "https://www.rust-lang.org", "-t", "rust"],
+    );
+    tsd(
+        dir.path(),
+        &[
+            "add",
+            "https://doc.rust-lang.org/book",
+            "-c",
+            "The Book",
+            "-t",
+            "rust,docs",
+        ],
+    );
+    tsd(
+        dir.path(),
+        &["add", "https://go.dev", "-c", "Not rust", "-t", "go"],
+    );
+
+    let ids = |args: &[&str]| -> Vec<i64> {
+        let found: Value = serde_json::from_str(&tsd(dir.path(), args)).unwrap();
+        found
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|e| e["id"].as_i64().unwrap())
+            .collect()
+    };
+    assert_eq!(ids(&["--json", "search", "RUST"]), vec![1, 2, 3]);
+    assert_eq!(
+        ids(&["--json", "search", "rust", "--tag", "rust"]),
+        vec![1, 2]
+    );
+    assert_eq!(ids(&["--json", "search", "book", "-t", "docs"]), vec![2]);
+    assert_eq!(
+        ids(&["--json", "search", "book", "-t", "go"]),
+        Vec::<i64>::new()
+    );
+
+    let table = tsd(dir.path(), &["search", "book"]);
+    assert!(table.starts_with("ID"));
+    assert!(table.contains("#2  https://doc.rust-lang.org/book  The Book"));
+    assert_eq!(
+        tsd(dir.path(), &["search", "python"]).trim(),
+        "No matches for \"python\"."
+    );
+}
+
+#[test]
+fn dry_run_prune() {
+    let dir = tempfile::tempdir().unwrap();
+    tsd(dir.path(), &["add", "https://example.com", "-t", "used"]);
+    {
+        let db = Database::open_from_path(dir.path().join("tsd.db")).unwrap();
+        db.add_tag("unused").unwrap();
+        db.add_tag("also unused").unwrap();
+    }
+
+    assert_eq!(
+        tsd(dir.path(), &["prune", "--dry-run"]),
+        "Would remove 2 unused tags.\n    also unused\n    unused\n"
+    );
+    let db = Database::open_from_path(dir.path().join("tsd.db")).unwrap();
+    assert_eq!(
+        db.list_tags().unwrap(),
+        vec!["also unused", "unused", "used"]
+    );
+
+    assert_eq!(tsd(dir.path(), &["prune"]), "Removed 2 unused tags.\n");
+    assert_eq!(db.list_tags().unwrap(), vec!["used"]);
+}
+
+#[test]
+fn dry_run_archive_all_and_dedupe() {
+    let dir = tempfile::tempdir().unwrap();
+    {
+        let db = Database::open_from_path(dir.path().join("tsd.db")).unwrap();
+        let entry = || {
+            EntryBuilder::new()
+                .link("https://example.com")
+                .build()
+                .unwrap()
+        };
+        db.add_entry_allow_duplicate(entry()).unwrap();
+        db.add_entry_allow_duplicate(entry()).unwrap();
+    }
+
+    let archive = tsd(dir.path(), &["archive-all", "--dry-run"]);
+    assert!(archive.starts_with("Would move 2 links from dump to archive.\n"));
+    assert_eq!(
+        tsd(dir.path(), &["dedupe", "--dry-run"]),
+        "Would merge 1 duplicated links.\n    https://example.com (#1, #2)\n"
+    );
+
+    let db = Database::open_from_path(dir.path().join("tsd.db")).unwrap();
+    assert_eq!(db.count_entries(), Ok(2));
+    assert_eq!(db.stats().unwrap().queued, 2);
+}
+
+#[test]
+fn no_subcommand_prints_usage() {
+    let dir = tempfile::tempdir().unwrap();
+    let database = dir.path().join("data").join("tsd.db");
+    let config = dir.path().join("config.toml");
+    fs::write(&config, format!("database_path = {:?}\n", database)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_tsd"))
+        .arg("--config")
+        .arg(&config)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let printed = String::from_utf8_lossy(&output.stdout) + String::from_utf8_lossy(&output.stderr);
+    assert!(
+        printed.contains("USAGE"),
+        "expected usage, got: {}",
+        printed
+    );
+    assert!(!database.parent().unwrap().exists());
+}
+
+#[test]
+fn dry_run_only_where_supported() {
+    let dir = tempfile::tempdir().unwrap();
+    tsd(dir.path(), &["add", "https://example.com"]);
+    let config = dir.path().join("config.toml");
+
+    for args in &[
+        &["--dry-run", "read", "1"][..],
+        &["read", "1", "--dry-run"][..],
+    ] {
+        let output = Command::new(env!("CARGO_BIN_EXE_tsd"))
+            .arg("--config")
+            .arg(&config)
+            .args(args.iter())
+            .output()
+            .unwrap();
+        assert!(!output.status.success(), "tsd {:?} should fail", args);
+    }
+
+    // Nothing should have been read
+    assert_eq!(
+        tsd(dir.path(), &["--json", "list"])
+            .matches("\"id\"")
+            .count(),
+        1
+    );
+}